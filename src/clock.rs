@@ -0,0 +1,168 @@
+use std::time::Duration;
+use schackmotor::Color;
+use crate::protocol::{ClockState, TimeControl};
+
+#[derive(Clone)]
+pub(crate) struct Clock {
+    time_control: TimeControl,
+    white_remaining: Duration,
+    black_remaining: Duration,
+    first_to_move: Color,
+    to_move: Color
+}
+
+impl Clock {
+    pub(crate) fn new(time_control: TimeControl, first_to_move: Color) -> Self {
+        Clock {
+            time_control,
+            white_remaining: time_control.base(),
+            black_remaining: time_control.base(),
+            first_to_move,
+            to_move: first_to_move
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        *self = Clock::new(self.time_control, self.first_to_move);
+    }
+
+    pub(crate) fn get_time_control(&self) -> TimeControl {
+        self.time_control
+    }
+
+    pub(crate) fn get_to_move(&self) -> Color {
+        self.to_move
+    }
+
+    pub(crate) fn remaining(&self, color: Color) -> Duration {
+        match color {
+            Color::White => self.white_remaining,
+            Color::Black => self.black_remaining
+        }
+    }
+
+    fn remaining_mut(&mut self, color: Color) -> &mut Duration {
+        match color {
+            Color::White => &mut self.white_remaining,
+            Color::Black => &mut self.black_remaining
+        }
+    }
+
+    //Runs down the clock of the player to move, returns whether their flag has fallen
+    pub(crate) fn tick(&mut self, elapsed: Duration) -> bool {
+        let remaining = self.remaining_mut(self.to_move);
+        *remaining = remaining.checked_sub(elapsed).unwrap_or_default();
+        *remaining == Duration::from_secs(0)
+    }
+
+    pub(crate) fn moved(&mut self) {
+        let increment = self.time_control.increment();
+        *self.remaining_mut(self.to_move) += increment;
+        self.to_move = self.to_move.invert();
+    }
+
+    pub(crate) fn state(&self) -> ClockState {
+        ClockState {
+            white_ms: self.white_remaining.as_millis() as u64,
+            black_ms: self.black_remaining.as_millis() as u64
+        }
+    }
+
+    //Takes over the time the player who just moved reported for themselves, since they measured
+    //their own thinking time. Returns how far our copy of their clock had drifted.
+    pub(crate) fn sync(&mut self, state: &ClockState) -> Duration {
+        let mover = self.to_move.invert();
+        let reported = Duration::from_millis(match mover {
+            Color::White => state.white_ms,
+            Color::Black => state.black_ms
+        });
+
+        let remaining = self.remaining_mut(mover);
+        let drift = if *remaining > reported { *remaining - reported } else { reported - *remaining };
+        *remaining = reported;
+
+        drift
+    }
+}
+
+pub(crate) fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds < 10 {
+        format!("0:{:02}.{}", seconds, duration.subsec_millis() / 100)
+    } else {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clock_with(base_seconds: u64, increment_seconds: u64) -> Clock {
+        Clock::new(TimeControl { base_seconds, increment_seconds }, Color::White)
+    }
+
+    #[test]
+    fn only_the_player_to_move_loses_time() {
+        let mut clock = clock_with(60, 0);
+        assert!(!clock.tick(Duration::from_secs(10)));
+        assert_eq!(clock.remaining(Color::White), Duration::from_secs(50));
+        assert_eq!(clock.remaining(Color::Black), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn flag_falls_at_zero() {
+        let mut clock = clock_with(60, 0);
+        assert!(!clock.tick(Duration::from_millis(59_999)));
+        assert!(clock.tick(Duration::from_millis(1)));
+
+        let mut clock = clock_with(1, 0);
+        assert!(clock.tick(Duration::from_secs(5)));
+        assert_eq!(clock.remaining(Color::White), Duration::from_secs(0));
+    }
+
+    #[test]
+    fn moving_adds_the_increment_and_passes_the_turn() {
+        let mut clock = clock_with(60, 3);
+        clock.tick(Duration::from_secs(10));
+        clock.moved();
+        assert_eq!(clock.remaining(Color::White), Duration::from_secs(53));
+        assert!(clock.get_to_move() == Color::Black);
+
+        clock.tick(Duration::from_secs(1));
+        assert_eq!(clock.remaining(Color::Black), Duration::from_secs(59));
+        assert_eq!(clock.remaining(Color::White), Duration::from_secs(53));
+    }
+
+    #[test]
+    fn sync_takes_the_movers_time_and_reports_drift() {
+        let mut clock = clock_with(60, 0);
+        clock.tick(Duration::from_secs(10));
+        clock.moved();
+
+        let drift = clock.sync(&ClockState { white_ms: 49_750, black_ms: 1 });
+        assert_eq!(drift, Duration::from_millis(250));
+        assert_eq!(clock.remaining(Color::White), Duration::from_millis(49_750));
+        //Only the mover's clock is taken over
+        assert_eq!(clock.remaining(Color::Black), Duration::from_secs(60));
+
+        let drift = clock.sync(&ClockState { white_ms: 50_000, black_ms: 1 });
+        assert_eq!(drift, Duration::from_millis(250));
+    }
+
+    #[test]
+    fn reset_starts_over() {
+        let mut clock = clock_with(60, 2);
+        clock.tick(Duration::from_secs(10));
+        clock.moved();
+        clock.reset();
+        assert_eq!(clock.state(), ClockState { white_ms: 60_000, black_ms: 60_000 });
+        assert!(clock.get_to_move() == Color::White);
+    }
+
+    #[test]
+    fn formats_tenths_under_ten_seconds() {
+        assert_eq!(format_duration(Duration::from_millis(9_450)), "0:09.4");
+        assert_eq!(format_duration(Duration::from_secs(75)), "1:15");
+    }
+}
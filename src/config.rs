@@ -0,0 +1,274 @@
+use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
+use crate::engine::MAX_LEVEL;
+use crate::fen::Fen;
+use crate::protocol::{ColorChoice, TimeControl};
+use crate::settings::Settings;
+
+const USAGE: &str = "usage: schack_gui [--bind ADDRESS] [--port PORT] [--color COLOR] [--time MINUTES+INCREMENT] [--fen FEN] [--pgn-dir DIR] [--headless] --peer HOST:PORT
+       schack_gui --local [--flip] [--time MINUTES+INCREMENT] [--fen FEN] [--pgn-dir DIR]
+       schack_gui --engine LEVEL [--color COLOR] [--time MINUTES+INCREMENT] [--fen FEN] [--pgn-dir DIR]
+       schack_gui --uci PATH [--color COLOR] [--time MINUTES+INCREMENT] [--fen FEN] [--pgn-dir DIR]
+       schack_gui --replay FILE
+
+Every form also takes [--animation MS] [--autoqueen] [--board THEME] [--pieces SET].
+The defaults below can be changed in schack/settings.json in the user's config directory.
+
+options:
+    --bind ADDRESS     address to listen on (env SCHACK_BIND, default 0.0.0.0)
+    --port PORT        port to listen on (env SCHACK_PORT, default 7878)
+    --peer HOST:PORT   address of the opponent's client (env SCHACK_PEER)
+    --color COLOR      white, black or random, for yourself (env SCHACK_COLOR, default random)
+    --time M+S         clock with M minutes and S seconds increment, e.g. 5+3 (env SCHACK_TIME, default no clock)
+    --fen FEN          start from this position instead of the standard one (env SCHACK_FEN)
+    --pgn-dir DIR      where finished games are saved as PGN (env SCHACK_PGN_DIR, default games)
+    --headless         play from the terminal instead of opening a window
+    --local            play both colors in this window instead of against a peer
+    --engine LEVEL     play against the built-in engine, from 1 (weakest) to 4 (env SCHACK_ENGINE)
+    --uci PATH         play against a UCI engine run from PATH (env SCHACK_UCI)
+    --analyze PATH     show the evaluation of a UCI engine run from PATH while playing (env SCHACK_ANALYZE)
+    --flip             turn the board towards the player to move after every move of a local game
+    --replay FILE      step through a game saved as PGN instead of playing (env SCHACK_REPLAY)
+    --animation MS     how long a move takes to slide across the board, 0 for none (env SCHACK_ANIMATION, default 200)
+    --autoqueen        always promote pawns to queens instead of asking
+    --board THEME      board colors from resources/boards/THEME.json (env SCHACK_BOARD, default classic)
+    --pieces SET       piece images from resources/pieces/SET (env SCHACK_PIECES, default classic)
+    --help             print this message";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Config {
+    pub(crate) bind_address: String,
+    pub(crate) listen_port: u16,
+    pub(crate) peer_address: String,
+    pub(crate) color: ColorChoice,
+    pub(crate) time_control: Option<TimeControl>,
+    pub(crate) start_position: Option<Fen>,
+    pub(crate) pgn_directory: PathBuf,
+    pub(crate) headless: bool,
+    pub(crate) local: bool,
+    pub(crate) flip: bool,
+    pub(crate) engine_level: Option<u8>,
+    pub(crate) uci_engine: Option<String>,
+    pub(crate) analyzer: Option<String>,
+    pub(crate) replay: Option<PathBuf>,
+    pub(crate) animation_duration: Duration,
+    pub(crate) always_queen: bool,
+    pub(crate) board_theme: String,
+    pub(crate) piece_set: String,
+    pub(crate) flip_board: bool,
+    pub(crate) confirm_moves: bool,
+    pub(crate) settings: Settings //as read from the settings file, to be saved again when changed from the window
+}
+
+impl Config {
+    pub(crate) fn listen_address(&self) -> String {
+        format!("{}:{}", self.bind_address, self.listen_port)
+    }
+
+    pub(crate) fn from_env_and_args() -> Result<Config, String> {
+        Config::parse(env::args().skip(1), |key| env::var(key).ok(), Settings::load())
+    }
+
+    //Command line options take precedence over environment variables, which take precedence over the settings file
+    fn parse<I: Iterator<Item = String>, F: Fn(&str) -> Option<String>>(mut args: I, env_var: F, settings: Settings) -> Result<Config, String> {
+        let mut bind_address = env_var("SCHACK_BIND");
+        let mut listen_port = env_var("SCHACK_PORT");
+        let mut peer_address = env_var("SCHACK_PEER");
+        let mut color = env_var("SCHACK_COLOR");
+        let mut time_control = env_var("SCHACK_TIME");
+        let mut start_position = env_var("SCHACK_FEN");
+        let mut pgn_directory = env_var("SCHACK_PGN_DIR");
+        let mut headless = false;
+        let mut local = false;
+        let mut flip = settings.flip;
+        let mut engine_level = env_var("SCHACK_ENGINE");
+        let mut uci_engine = env_var("SCHACK_UCI");
+        let mut analyzer = env_var("SCHACK_ANALYZE");
+        let mut replay = env_var("SCHACK_REPLAY");
+        let mut animation_duration = env_var("SCHACK_ANIMATION");
+        let mut always_queen = settings.always_queen;
+        let mut board_theme = env_var("SCHACK_BOARD");
+        let mut piece_set = env_var("SCHACK_PIECES");
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--bind" => bind_address = Some(option_value(&arg, args.next())?),
+                "--port" => listen_port = Some(option_value(&arg, args.next())?),
+                "--peer" => peer_address = Some(option_value(&arg, args.next())?),
+                "--color" => color = Some(option_value(&arg, args.next())?),
+                "--time" => time_control = Some(option_value(&arg, args.next())?),
+                "--fen" => start_position = Some(option_value(&arg, args.next())?),
+                "--pgn-dir" => pgn_directory = Some(option_value(&arg, args.next())?),
+                "--headless" => headless = true,
+                "--local" => local = true,
+                "--flip" => flip = true,
+                "--engine" => engine_level = Some(option_value(&arg, args.next())?),
+                "--uci" => uci_engine = Some(option_value(&arg, args.next())?),
+                "--analyze" => analyzer = Some(option_value(&arg, args.next())?),
+                "--replay" => replay = Some(option_value(&arg, args.next())?),
+                "--animation" => animation_duration = Some(option_value(&arg, args.next())?),
+                "--autoqueen" => always_queen = true,
+                "--board" => board_theme = Some(option_value(&arg, args.next())?),
+                "--pieces" => piece_set = Some(option_value(&arg, args.next())?),
+                "--help" | "-h" => return Err(USAGE.to_string()),
+                _ => return Err(format!("unknown option {}\n\n{}", arg, USAGE))
+            }
+        }
+
+        let listen_port = match listen_port {
+            Some(port) => port.parse::<u16>().map_err(|_| format!("invalid port \"{}\"", port))?,
+            None => settings.listen_port
+        };
+
+        let color = match color {
+            Some(color) => color.parse::<ColorChoice>()?,
+            None => settings.color.parse::<ColorChoice>()?
+        };
+
+        let time_control = match time_control {
+            Some(time_control) => Some(time_control.parse::<TimeControl>()?),
+            None => settings.get_time_control()?
+        };
+
+        let start_position = match start_position {
+            Some(start_position) => Some(start_position.parse::<Fen>()?),
+            None => None
+        };
+
+        let engine_level = match engine_level {
+            Some(level) => match level.parse::<u8>() {
+                Ok(level @ 1..=MAX_LEVEL) => Some(level),
+                _ => return Err(format!("invalid engine level \"{}\", expected 1 to {}", level, MAX_LEVEL))
+            },
+            None => None
+        };
+
+        let animation_duration = match animation_duration {
+            Some(ms) => Duration::from_millis(ms.parse::<u64>().map_err(|_| format!("invalid animation duration \"{}\"", ms))?),
+            None => Duration::from_millis(settings.animation_ms)
+        };
+
+        if local && headless {
+            return Err(format!("--local can't be combined with --headless\n\n{}", USAGE));
+        }
+        if engine_level.is_some() && uci_engine.is_some() {
+            return Err(format!("--engine can't be combined with --uci\n\n{}", USAGE));
+        }
+        let against_engine = engine_level.is_some() || uci_engine.is_some();
+        if against_engine && (local || headless) {
+            return Err(format!("--engine and --uci can't be combined with --local or --headless\n\n{}", USAGE));
+        }
+        if analyzer.is_some() && headless {
+            return Err(format!("--analyze can't be combined with --headless\n\n{}", USAGE));
+        }
+
+        //Local games, engine games and replays don't talk to anyone, so they need no peer
+        let peer_address = match peer_address.or_else(|| Some(settings.peer_address.clone()).filter(|address| !address.is_empty())) {
+            Some(peer_address) => peer_address,
+            None if local || against_engine || replay.is_some() => String::new(),
+            None => return Err(format!("no peer address given\n\n{}", USAGE))
+        };
+
+        Ok(Config {
+            bind_address: bind_address.unwrap_or_else(|| settings.bind_address.clone()),
+            listen_port,
+            peer_address,
+            color,
+            time_control,
+            start_position,
+            pgn_directory: PathBuf::from(pgn_directory.unwrap_or_else(|| settings.pgn_directory.clone())),
+            headless,
+            local,
+            flip,
+            engine_level,
+            uci_engine,
+            analyzer,
+            replay: replay.map(PathBuf::from),
+            animation_duration,
+            always_queen,
+            board_theme: board_theme.unwrap_or_else(|| settings.board_theme.clone()),
+            piece_set: piece_set.unwrap_or_else(|| settings.piece_set.clone()),
+            flip_board: settings.flip_board,
+            confirm_moves: settings.confirm_moves,
+            settings
+        })
+    }
+}
+
+fn option_value(option: &str, value: Option<String>) -> Result<String, String> {
+    value.ok_or_else(|| format!("missing value for {}\n\n{}", option, USAGE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str], env: &[(&str, &str)], settings: Settings) -> Result<Config, String> {
+        let env: Vec<(String, String)> = env.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect();
+        Config::parse(args.iter().map(|arg| arg.to_string()),
+                      |key| env.iter().find(|(name, _)| name == key).map(|(_, value)| value.clone()),
+                      settings)
+    }
+
+    fn settings() -> Settings {
+        Settings {
+            listen_port: 9000,
+            peer_address: "file:1".to_string(),
+            color: "black".to_string(),
+            pgn_directory: "file_games".to_string(),
+            board_theme: "green".to_string(),
+            animation_ms: 50,
+            confirm_moves: true,
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn settings_file_gives_the_defaults() {
+        let config = parse(&[], &[], settings()).unwrap();
+        assert_eq!(config.listen_port, 9000);
+        assert_eq!(config.peer_address, "file:1");
+        assert_eq!(config.color, ColorChoice::Black);
+        assert_eq!(config.pgn_directory, PathBuf::from("file_games"));
+        assert_eq!(config.board_theme, "green");
+        assert_eq!(config.animation_duration, Duration::from_millis(50));
+        assert!(config.confirm_moves);
+    }
+
+    #[test]
+    fn environment_beats_settings_file() {
+        let env = [("SCHACK_PORT", "9100"), ("SCHACK_PEER", "env:1"), ("SCHACK_COLOR", "white"), ("SCHACK_BOARD", "blue")];
+        let config = parse(&[], &env, settings()).unwrap();
+        assert_eq!(config.listen_port, 9100);
+        assert_eq!(config.peer_address, "env:1");
+        assert_eq!(config.color, ColorChoice::White);
+        assert_eq!(config.board_theme, "blue");
+        assert_eq!(config.pgn_directory, PathBuf::from("file_games"));
+    }
+
+    #[test]
+    fn command_line_beats_environment() {
+        let env = [("SCHACK_PORT", "9100"), ("SCHACK_PEER", "env:1"), ("SCHACK_COLOR", "white")];
+        let args = ["--port", "9200", "--peer", "arg:1", "--color", "random", "--animation", "0"];
+        let config = parse(&args, &env, settings()).unwrap();
+        assert_eq!(config.listen_port, 9200);
+        assert_eq!(config.peer_address, "arg:1");
+        assert_eq!(config.color, ColorChoice::Random);
+        assert_eq!(config.animation_duration, Duration::from_millis(0));
+    }
+
+    #[test]
+    fn needs_a_peer_from_somewhere() {
+        assert!(parse(&[], &[], Settings::default()).is_err());
+        assert!(parse(&["--local"], &[], Settings::default()).is_ok());
+    }
+
+    #[test]
+    fn rejects_invalid_options() {
+        assert!(parse(&["--port", "many"], &[], settings()).is_err());
+        assert!(parse(&[], &[("SCHACK_COLOR", "purple")], settings()).is_err());
+        assert!(parse(&["--time"], &[], settings()).is_err());
+        assert!(parse(&["--nonsense"], &[], settings()).is_err());
+    }
+}
@@ -0,0 +1,234 @@
+use std::sync::{Arc, Mutex};
+use std::thread;
+use schackmotor::{Board, Color, GameState, PieceType, Position};
+use crate::{DataHandler, NotatedMove};
+use crate::notation::position_from_square;
+use crate::uci::{self, UciEngine};
+
+pub(crate) const MAX_LEVEL: u8 = 4;
+
+const MATE_SCORE: i32 = 100_000;
+
+#[derive(Clone)]
+enum Search {
+    Minimax(u8), //how many plies deep to search
+    Uci(Arc<Mutex<UciEngine>>)
+}
+
+//A computer opponent, either searching the game tree itself with minimax and alpha-beta pruning or
+//asking a UCI engine. Every search runs on its own thread and plays its move through
+//DataHandler::receive_move, like a move from the network.
+pub(crate) struct Engine {
+    color: Color,
+    name: String,
+    search: Search,
+    thinking: Arc<Mutex<bool>>
+}
+
+impl Engine {
+    pub(crate) fn new(color: Color, level: u8) -> Self {
+        let level = level.clamp(1, MAX_LEVEL);
+        Engine {
+            color,
+            name: format!("Schack engine level {}", level),
+            search: Search::Minimax(level),
+            thinking: Arc::new(Mutex::new(false))
+        }
+    }
+
+    pub(crate) fn with_uci(color: Color, uci_engine: UciEngine) -> Self {
+        Engine {
+            color,
+            name: uci_engine.get_name(),
+            search: Search::Uci(Arc::new(Mutex::new(uci_engine))),
+            thinking: Arc::new(Mutex::new(false))
+        }
+    }
+
+    pub(crate) fn get_color(&self) -> Color {
+        self.color
+    }
+
+    pub(crate) fn is_thinking(&self) -> bool {
+        *self.thinking.lock().unwrap()
+    }
+
+    pub(crate) fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    //Starts searching for a move if it is the engine's turn and it isn't searching already
+    pub(crate) fn update(&mut self, data_handler: &Arc<Mutex<DataHandler>>) {
+        if self.is_thinking() {
+            return;
+        }
+
+        let (board, start_position, moves, go) = {
+            let data_handler = data_handler.lock().unwrap();
+            if data_handler.is_game_over() || data_handler.board.get_current_player() != self.color {
+                return;
+            }
+            (data_handler.board.clone(), data_handler.start_position.clone(),
+             data_handler.history.iter().map(|played| played.notated.clone()).collect::<Vec<_>>(),
+             uci::go_command(data_handler.clock.as_ref()))
+        };
+
+        *self.thinking.lock().unwrap() = true;
+
+        let data_handler = data_handler.clone();
+        let thinking = self.thinking.clone();
+        let color = self.color;
+        let search = self.search.clone();
+
+        thread::spawn(move || {
+            let best = match &search {
+                Search::Minimax(depth) => Ok(best_move(&board, color, *depth)),
+                Search::Uci(uci_engine) => uci_engine.lock().unwrap().best_move(&start_position, &moves, &go).map(Some)
+            };
+
+            {
+                let mut data_handler = data_handler.lock().unwrap();
+                //The game may have been reset or ended while the engine was thinking
+                let still_to_move = data_handler.history.len() == moves.len() && !data_handler.is_game_over()
+                    && data_handler.board.get_current_player() == color;
+                match best {
+                    Ok(Some(mov)) if still_to_move => {
+                        //Searching the same position again would only find the same move
+                        if let Err(e) = data_handler.receive_move(mov, color) {
+                            println!("Engine move rejected: {}", e);
+                            data_handler.resign(color);
+                        }
+                    }
+                    Ok(_) => {}
+                    //An engine that can't move anymore would otherwise be asked again every frame
+                    Err(e) => {
+                        println!("{}", e);
+                        data_handler.resign(color);
+                    }
+                }
+            }
+
+            *thinking.lock().unwrap() = false;
+        });
+    }
+}
+
+//The move with the best minimax score for `color`, picking at random between equally good ones
+pub(crate) fn best_move(board: &Board, color: Color, depth: u8) -> Option<NotatedMove> {
+    let mut best_moves = Vec::new();
+    let mut best_score = -MATE_SCORE * 2;
+
+    for mov in legal_moves(board, color) {
+        let mut next = board.clone();
+        if next.take_move(mov.to_string()).is_err() {
+            continue;
+        }
+
+        let score = -negamax(&next, color.invert(), depth.saturating_sub(1), -MATE_SCORE * 2, -best_score + 1);
+        if score > best_score {
+            best_score = score;
+            best_moves.clear();
+        }
+        if score == best_score {
+            best_moves.push(mov);
+        }
+    }
+
+    if best_moves.is_empty() {
+        None
+    } else {
+        let index = rand::random::<usize>() % best_moves.len();
+        Some(best_moves.swap_remove(index))
+    }
+}
+
+//The score of the position for the player to move, searched `depth` plies deep
+fn negamax(board: &Board, color: Color, depth: u8, mut alpha: i32, beta: i32) -> i32 {
+    match board.get_game_state() {
+        //Being mated with more depth left means being mated sooner
+        GameState::Checkmate(_) => return -MATE_SCORE - depth as i32,
+        GameState::Draw => return 0,
+        GameState::Normal | GameState::Check(_) => {}
+    }
+
+    if depth == 0 {
+        return evaluate(board, color);
+    }
+
+    let mut best_score = -MATE_SCORE * 2;
+    for mov in legal_moves(board, color) {
+        let mut next = board.clone();
+        if next.take_move(mov.to_string()).is_err() {
+            continue;
+        }
+
+        let score = -negamax(&next, color.invert(), depth - 1, -beta, -alpha);
+        best_score = best_score.max(score);
+        alpha = alpha.max(score);
+        if alpha >= beta {
+            break;
+        }
+    }
+
+    best_score
+}
+
+//Every move `color` can make, captures first since they are the likeliest to cut the search short.
+//Pawns only promote to queens and knights, the other pieces are never better than a queen.
+fn legal_moves(board: &Board, color: Color) -> Vec<NotatedMove> {
+    let mut moves = Vec::new();
+
+    for piece in board.get_pieces() {
+        if piece.get_color() != color {
+            continue;
+        }
+
+        let start = piece.get_position();
+        for (end, promotes) in board.get_possible_moves_from_position(start).unwrap_or_default() {
+            if promotes {
+                moves.push(NotatedMove::new(start.to_string(), end.to_string(), Some("Q".to_string())));
+                moves.push(NotatedMove::new(start.to_string(), end.to_string(), Some("N".to_string())));
+            } else {
+                moves.push(NotatedMove::new(start.to_string(), end.to_string(), None));
+            }
+        }
+    }
+
+    moves.sort_by_key(|mov| position_from_square(&mov.end_position)
+        .is_none_or(|end| board.get_piece_at(end).is_none()));
+
+    moves
+}
+
+//Material, with small bonuses for advanced pawns and centralized knights and bishops,
+//from the point of view of `color`
+fn evaluate(board: &Board, color: Color) -> i32 {
+    board.get_pieces().iter()
+        .map(|piece| {
+            let value = piece_value(piece.get_type()) + position_bonus(piece.get_type(), piece.get_color(), piece.get_position());
+            if piece.get_color() == color { value } else { -value }
+        })
+        .sum()
+}
+
+fn piece_value(piece_type: PieceType) -> i32 {
+    match piece_type {
+        PieceType::Pawn => 100,
+        PieceType::Knight => 320,
+        PieceType::Bishop => 330,
+        PieceType::Rook => 500,
+        PieceType::Queen => 900,
+        PieceType::King => 0
+    }
+}
+
+fn position_bonus(piece_type: PieceType, color: Color, position: Position) -> i32 {
+    let (x, y) = (position.get_x() as i32, position.get_y() as i32);
+    let center_distance = (2 * x - 9).abs().max((2 * y - 9).abs());
+
+    match piece_type {
+        PieceType::Pawn => 5 * if color == Color::White { y - 2 } else { 7 - y },
+        PieceType::Knight | PieceType::Bishop => 4 * (7 - center_distance),
+        PieceType::Rook | PieceType::Queen | PieceType::King => 0
+    }
+}
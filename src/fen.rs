@@ -0,0 +1,376 @@
+use std::fmt;
+use std::str::FromStr;
+use schackmotor::{Board, Color, Piece, PieceType, Position};
+use crate::notation::{self, PlayedMove};
+
+pub(crate) const STANDARD_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+//A position in Forsyth-Edwards Notation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Fen {
+    placement: String,
+    black_to_move: bool,
+    castling: String,
+    en_passant: Option<String>,
+    halfmove_clock: u32,
+    fullmove_number: u32
+}
+
+impl Fen {
+    pub(crate) fn standard() -> Self {
+        STANDARD_FEN.parse().unwrap()
+    }
+
+    pub(crate) fn is_standard(&self) -> bool {
+        self.to_string() == STANDARD_FEN
+    }
+
+    pub(crate) fn get_to_move(&self) -> Color {
+        if self.black_to_move { Color::Black } else { Color::White }
+    }
+
+    pub(crate) fn get_fullmove_number(&self) -> u32 {
+        self.fullmove_number
+    }
+
+    fn pieces(&self) -> Vec<(Color, PieceType, Position)> {
+        let mut pieces = Vec::new();
+
+        for (rank_index, rank) in self.placement.split('/').enumerate() {
+            let mut file = 1;
+            for c in rank.chars() {
+                if let Some(empty) = c.to_digit(10) {
+                    file += empty as u8;
+                } else {
+                    let color = if c.is_ascii_uppercase() { Color::White } else { Color::Black };
+                    pieces.push((color, piece_type(c).unwrap(), Position::new(file, 8 - rank_index as u8)));
+                    file += 1;
+                }
+            }
+        }
+
+        pieces
+    }
+
+    //The castling rights a board set up from the pieces has, every king and rook still on its starting square
+    fn board_castling(&self) -> String {
+        let pieces = self.pieces();
+        let stands_on = |color: Color, piece_type: PieceType, square: &str| pieces.iter()
+            .any(|(c, t, position)| *c == color && *t == piece_type && position.to_string() == square);
+
+        [('K', Color::White, "e1", "h1"), ('Q', Color::White, "e1", "a1"), ('k', Color::Black, "e8", "h8"), ('q', Color::Black, "e8", "a8")]
+            .iter()
+            .filter(|(_, color, king, rook)| stands_on(*color, PieceType::King, king) && stands_on(*color, PieceType::Rook, rook))
+            .map(|(right, _, _, _)| *right)
+            .collect()
+    }
+
+    //Whether a pawn stands next to the pawn that skipped over `square`, ready to take it
+    fn can_capture_en_passant(&self, square: &str) -> bool {
+        let target = match notation::position_from_square(square) {
+            Some(target) => target,
+            None => return false
+        };
+        let (color, rank) = if target.get_y() == 3 { (Color::Black, 4) } else { (Color::White, 5) };
+
+        self.pieces().iter().any(|(c, t, position)| *c == color && *t == PieceType::Pawn && position.get_y() == rank
+            && (position.get_x() as i8 - target.get_x() as i8).abs() == 1)
+    }
+
+    pub(crate) fn to_board(&self) -> Board {
+        let layout = self.pieces().into_iter()
+            .map(|(color, piece_type, position)| Piece::new(piece_type, color, position))
+            .collect();
+        let mut board = Board::new(layout);
+        board.set_current_player(self.get_to_move());
+        board
+    }
+
+    //The position reached after `history` was played from `start` and led to `board`. Castling rights
+    //are lost once a king or rook leaves, or something arrives at, its starting square.
+    pub(crate) fn from_game(start: &Fen, board: &Board, history: &[PlayedMove]) -> Fen {
+        let mut ranks = Vec::new();
+        for y in (1..=8).rev() {
+            let mut rank = String::new();
+            let mut empty = 0;
+            for x in 1..=8 {
+                match board.get_piece_at(Position::new(x, y)) {
+                    Some(piece) => {
+                        if empty > 0 {
+                            rank.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        rank.push(piece_char(piece.get_color(), piece.get_type()));
+                    }
+                    None => empty += 1
+                }
+            }
+            if empty > 0 {
+                rank.push_str(&empty.to_string());
+            }
+            ranks.push(rank);
+        }
+
+        let touched = |square: &str| history.iter()
+            .any(|played| played.notated.start_position == square || played.notated.end_position == square);
+        let castling: String = start.castling.chars()
+            .filter(|right| {
+                let (king, rook) = match right {
+                    'K' => ("e1", "h1"),
+                    'Q' => ("e1", "a1"),
+                    'k' => ("e8", "h8"),
+                    _ => ("e8", "a8")
+                };
+                !touched(king) && !touched(rook)
+            })
+            .collect();
+
+        let en_passant = match history.last() {
+            Some(last) if last.san.starts_with(|c: char| c.is_ascii_lowercase()) => {
+                let (start_rank, end_rank) = (last.notated.start_position.as_bytes()[1], last.notated.end_position.as_bytes()[1]);
+                if (start_rank as i8 - end_rank as i8).abs() == 2 {
+                    Some(format!("{}{}", &last.notated.start_position[0..1], ((start_rank + end_rank) / 2) as char))
+                } else {
+                    None
+                }
+            }
+            Some(_) => None,
+            None => start.en_passant.clone()
+        };
+
+        //Pawn moves have SAN starting with the file, captures contain an x
+        let halfmove_clock = match history.iter().rposition(|played| played.san.contains('x')
+            || played.san.starts_with(|c: char| c.is_ascii_lowercase())) {
+            Some(index) => (history.len() - index - 1) as u32,
+            None => start.halfmove_clock + history.len() as u32
+        };
+
+        let black_moves = if start.black_to_move { (history.len() + 1) / 2 } else { history.len() / 2 };
+
+        Fen {
+            placement: ranks.join("/"),
+            black_to_move: board.get_current_player() == Color::Black,
+            castling: if castling.is_empty() { "-".to_string() } else { castling },
+            en_passant,
+            halfmove_clock,
+            fullmove_number: start.fullmove_number + black_moves as u32
+        }
+    }
+}
+
+impl FromStr for Fen {
+    type Err = String;
+
+    //The halfmove clock and fullmove number may be left out, as some tools do
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split_whitespace().collect();
+        if fields.len() != 4 && fields.len() != 6 {
+            return Err(format!("Invalid FEN \"{}\", expected 6 fields", s.trim()));
+        }
+
+        let ranks: Vec<&str> = fields[0].split('/').collect();
+        if ranks.len() != 8 {
+            return Err(format!("Invalid FEN placement \"{}\", expected 8 ranks", fields[0]));
+        }
+        for (rank_index, rank) in ranks.iter().enumerate() {
+            let mut files = 0;
+            for c in rank.chars() {
+                match c.to_digit(10) {
+                    Some(empty @ 1..=8) => files += empty,
+                    Some(_) => return Err(format!("Invalid FEN rank \"{}\"", rank)),
+                    None if piece_type(c).is_none() => return Err(format!("Invalid FEN piece '{}'", c)),
+                    None if (c == 'P' || c == 'p') && (rank_index == 0 || rank_index == 7) => {
+                        return Err("Pawns can't stand on the first or last rank".to_string());
+                    }
+                    None => files += 1
+                }
+            }
+            if files != 8 {
+                return Err(format!("Invalid FEN rank \"{}\", expected 8 files", rank));
+            }
+        }
+        if fields[0].matches('K').count() != 1 || fields[0].matches('k').count() != 1 {
+            return Err("A FEN position needs exactly one king of each color".to_string());
+        }
+
+        let black_to_move = match fields[1] {
+            "w" => false,
+            "b" => true,
+            side => return Err(format!("Invalid side to move \"{}\"", side))
+        };
+
+        let castling = fields[2];
+        if castling != "-" && (castling.is_empty() || !castling.chars().all(|c| "KQkq".contains(c))) {
+            return Err(format!("Invalid castling rights \"{}\"", castling));
+        }
+
+        let en_passant = match fields[3] {
+            "-" => None,
+            square if notation::position_from_square(square).is_some() && (square.ends_with('3') || square.ends_with('6')) => {
+                Some(square.to_string())
+            }
+            square => return Err(format!("Invalid en passant square \"{}\"", square))
+        };
+
+        let (halfmove_clock, fullmove_number) = if fields.len() == 6 {
+            (fields[4].parse::<u32>().map_err(|_| format!("Invalid halfmove clock \"{}\"", fields[4]))?,
+             fields[5].parse::<u32>().ok().filter(|number| *number > 0)
+                 .ok_or_else(|| format!("Invalid fullmove number \"{}\"", fields[5]))?)
+        } else {
+            (0, 1)
+        };
+
+        let fen = Fen {
+            placement: fields[0].to_string(),
+            black_to_move,
+            castling: castling.to_string(),
+            en_passant,
+            halfmove_clock,
+            fullmove_number
+        };
+
+        //The board is set up from the pieces alone, so it can only play positions whose castling and
+        //en passant rights follow from where the pieces stand
+        let board_castling = fen.board_castling();
+        if "KQkq".chars().any(|right| fen.castling.contains(right) != board_castling.contains(right)) {
+            return Err(format!("Castling rights \"{}\" can't be set up, with these pieces they would be \"{}\"",
+                               fen.castling, if board_castling.is_empty() { "-" } else { board_castling.as_str() }));
+        }
+        if let Some(square) = &fen.en_passant {
+            if fen.can_capture_en_passant(square) {
+                return Err(format!("The en passant capture on {} can't be set up", square));
+            }
+        }
+
+        Ok(fen)
+    }
+}
+
+impl fmt::Display for Fen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {} {} {} {}", self.placement, if self.black_to_move { "b" } else { "w" }, self.castling,
+               self.en_passant.as_ref().map_or("-", |square| square.as_str()), self.halfmove_clock, self.fullmove_number)
+    }
+}
+
+fn piece_type(c: char) -> Option<PieceType> {
+    match c.to_ascii_uppercase() {
+        'K' => Some(PieceType::King),
+        'Q' => Some(PieceType::Queen),
+        'R' => Some(PieceType::Rook),
+        'B' => Some(PieceType::Bishop),
+        'N' => Some(PieceType::Knight),
+        'P' => Some(PieceType::Pawn),
+        _ => None
+    }
+}
+
+fn piece_char(color: Color, piece_type: PieceType) -> char {
+    let c = match notation::piece_letter(piece_type) {
+        "" => 'P',
+        letter => letter.chars().next().unwrap()
+    };
+    if color == Color::White { c } else { c.to_ascii_lowercase() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NotatedMove;
+
+    //Plays (from, to, san) moves from `start` and returns the FEN of the position reached
+    fn after_moves(start: &Fen, moves: &[(&str, &str, &str)]) -> Fen {
+        let mut board = start.to_board();
+        let mut history = Vec::new();
+        for (from, to, san) in moves {
+            let notated = NotatedMove::new(from.to_string(), to.to_string(), None);
+            board.take_move(notated.to_string()).unwrap();
+            history.push(PlayedMove { notated, san: san.to_string() });
+        }
+        Fen::from_game(start, &board, &history)
+    }
+
+    #[test]
+    fn standard_round_trip() {
+        let fen: Fen = STANDARD_FEN.parse().unwrap();
+        assert_eq!(fen.to_string(), STANDARD_FEN);
+        assert!(fen.is_standard());
+        assert!(fen.get_to_move() == Color::White);
+        assert_eq!(fen.get_fullmove_number(), 1);
+    }
+
+    #[test]
+    fn black_to_move() {
+        let text = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1";
+        let fen: Fen = text.parse().unwrap();
+        assert!(fen.get_to_move() == Color::Black);
+        assert_eq!(fen.to_string(), text);
+        assert!(!fen.is_standard());
+        assert!(fen.to_board().get_current_player() == Color::Black);
+    }
+
+    #[test]
+    fn counters_may_be_left_out() {
+        let fen: Fen = "4k3/8/8/8/8/8/8/4K3 w - -".parse().unwrap();
+        assert_eq!(fen.to_string(), "4k3/8/8/8/8/8/8/4K3 w - - 0 1");
+    }
+
+    #[test]
+    fn rejects_bad_fens() {
+        for text in &[
+            "rnbqkbnr/pppppppp/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", //seven ranks
+            "rnbqkbnr/ppppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", //nine files
+            "rnbqkbnr/pppppppp/9/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/pppppppp/7/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/pppppppp/8/8/3X4/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNP w KQkq - 0 1", //pawn on the first rank
+            "rnbq1bnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", //no black king
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR x KQkq - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkx - 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq e4 0 1",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 0",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0"
+        ] {
+            assert!(text.parse::<Fen>().is_err(), "accepted {}", text);
+        }
+    }
+
+    #[test]
+    fn rejects_castling_rights_the_board_cant_have() {
+        //The board would still castle with the kings and rooks at home
+        assert!("r3k2r/8/8/8/8/8/8/R3K2R w - - 0 1".parse::<Fen>().is_err());
+        assert!("r3k2r/8/8/8/8/8/8/R3K2R w Kkq - 0 1".parse::<Fen>().is_err());
+        //and can't without them
+        assert!("r3k3/8/8/8/8/8/8/R3K3 w KQkq - 0 1".parse::<Fen>().is_err());
+        assert!("r3k3/8/8/8/8/8/8/R3K3 w Qq - 0 1".parse::<Fen>().is_ok());
+        assert!("4k3/8/8/8/8/8/8/4K3 w - - 0 1".parse::<Fen>().is_ok());
+    }
+
+    #[test]
+    fn rejects_en_passant_captures_the_board_cant_play() {
+        assert!("4k3/8/8/8/3pP3/8/8/4K3 b - e3 0 1".parse::<Fen>().is_err());
+        assert!("4k3/8/8/4Pp2/8/8/8/4K3 w - f6 0 1".parse::<Fen>().is_err());
+        //Nothing can take the pawn, so the square makes no difference
+        assert!("4k3/8/8/8/4P3/8/8/4K3 b - e3 0 1".parse::<Fen>().is_ok());
+    }
+
+    #[test]
+    fn double_pawn_step_sets_en_passant() {
+        let fen = after_moves(&Fen::standard(), &[("e2", "e4", "e4")]);
+        assert_eq!(fen.to_string(), "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1");
+
+        let fen = after_moves(&Fen::standard(), &[("e2", "e4", "e4"), ("g8", "f6", "Nf6")]);
+        assert_eq!(fen.to_string(), "rnbqkb1r/pppppppp/5n2/8/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 1 2");
+    }
+
+    #[test]
+    fn moved_kings_and_rooks_lose_castling_rights() {
+        let start: Fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 5 10".parse().unwrap();
+
+        let fen = after_moves(&start, &[("a1", "a2", "Ra2")]);
+        assert_eq!(fen.to_string(), "r3k2r/8/8/8/8/8/R7/4K2R b Kkq - 6 10");
+
+        let fen = after_moves(&start, &[("a1", "a2", "Ra2"), ("e8", "e7", "Ke7")]);
+        assert_eq!(fen.to_string(), "r6r/4k3/8/8/8/8/R7/4K2R w K - 7 11");
+    }
+}
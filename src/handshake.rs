@@ -0,0 +1,266 @@
+use schackmotor::Color;
+use crate::fen::Fen;
+use crate::protocol::{ColorChoice, DeclineReason, PlayerColor, StartGameRequest, StartGameResponse, TimeControl};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum HandshakeState {
+    Idle,
+    Proposed(u64),
+    Playing,
+    Declined(DeclineReason), //the opponent declined our proposal
+    Refused(DeclineReason) //we declined the opponent's proposal
+}
+
+//Both clients may propose a game at the same time. The proposal with the higher nonce wins,
+//on equal nonces both proposals are declined and both sides try again with new ones.
+pub(crate) struct Handshake {
+    state: HandshakeState,
+    preference: ColorChoice,
+    time_control: Option<TimeControl>,
+    agreed_time_control: Option<TimeControl>,
+    start_position: Option<Fen>,
+    agreed_start_position: Option<Fen>
+}
+
+impl Handshake {
+    pub(crate) fn new(preference: ColorChoice, time_control: Option<TimeControl>, start_position: Option<Fen>) -> Self {
+        Handshake {
+            state: HandshakeState::Idle,
+            preference,
+            time_control,
+            agreed_time_control: None,
+            start_position,
+            agreed_start_position: None
+        }
+    }
+
+    //The time control of the game both players agreed on
+    pub(crate) fn get_time_control(&self) -> Option<TimeControl> {
+        self.agreed_time_control
+    }
+
+    //The position the game both players agreed on starts from
+    pub(crate) fn get_start_position(&self) -> Fen {
+        self.agreed_start_position.clone().unwrap_or_else(Fen::standard)
+    }
+
+    //Changes the position to propose, withdrawing a proposal of the old one
+    pub(crate) fn set_start_position(&mut self, start_position: Fen) -> Result<(), String> {
+        if self.state == HandshakeState::Playing {
+            return Err("The game has already started".to_string());
+        }
+
+        self.start_position = Some(start_position);
+        self.state = HandshakeState::Idle;
+        Ok(())
+    }
+
+    pub(crate) fn get_state(&self) -> &HandshakeState {
+        &self.state
+    }
+
+    //Lets a declined proposal be made again. When both players wanted the same color we give up
+    //our preference, as proposing it again would only clash again.
+    pub(crate) fn retry(&mut self) {
+        match self.state {
+            HandshakeState::Declined(DeclineReason::ColorTaken) | HandshakeState::Refused(DeclineReason::ColorTaken) => {
+                self.preference = ColorChoice::Random;
+            }
+            HandshakeState::Declined(_) | HandshakeState::Refused(_) => {}
+            _ => return
+        }
+        self.state = HandshakeState::Idle;
+    }
+
+    pub(crate) fn propose(&mut self) -> Option<StartGameRequest> {
+        if self.state != HandshakeState::Idle {
+            return None;
+        }
+
+        let nonce = rand::random::<u64>();
+        self.state = HandshakeState::Proposed(nonce);
+
+        Some(StartGameRequest {
+            color: self.preference,
+            nonce,
+            time_control: self.time_control,
+            fen: self.start_position.as_ref().map(|fen| fen.to_string())
+        })
+    }
+
+    //Returns the local player's color if the opponent accepted our proposal
+    pub(crate) fn answered(&mut self, nonce: u64, response: Result<StartGameResponse, String>) -> Option<Color> {
+        if self.state != HandshakeState::Proposed(nonce) {
+            return None;
+        }
+
+        match response {
+            Ok(StartGameResponse { accepted: true, color: Some(color), .. }) => {
+                self.state = HandshakeState::Playing;
+                self.agreed_time_control = self.time_control;
+                self.agreed_start_position = self.start_position.clone();
+                Some(color.into())
+            }
+            Ok(StartGameResponse { accepted: true, color: None, .. }) => {
+                self.state = HandshakeState::Declined(DeclineReason::Malformed);
+                None
+            }
+            Ok(StartGameResponse { reason: Some(DeclineReason::Crossed), .. }) | Err(_) => {
+                self.state = HandshakeState::Idle;
+                None
+            }
+            Ok(StartGameResponse { reason, .. }) => {
+                self.state = HandshakeState::Declined(reason.unwrap_or(DeclineReason::Malformed));
+                None
+            }
+        }
+    }
+
+    //Answers the opponent's proposal, returning the local player's color if it was accepted
+    pub(crate) fn receive(&mut self, request: &StartGameRequest) -> (StartGameResponse, Option<Color>) {
+        match self.state {
+            HandshakeState::Playing => return (StartGameResponse::declined(DeclineReason::InGame), None),
+            HandshakeState::Proposed(nonce) if request.nonce == nonce => {
+                self.state = HandshakeState::Idle;
+                return (StartGameResponse::declined(DeclineReason::Crossed), None);
+            }
+            HandshakeState::Proposed(nonce) if request.nonce < nonce => {
+                return (StartGameResponse::declined(DeclineReason::Crossed), None);
+            }
+            _ => {}
+        }
+
+        let their_color = match (request.color, self.preference) {
+            (ColorChoice::White, ColorChoice::White) | (ColorChoice::Black, ColorChoice::Black) => {
+                self.state = HandshakeState::Refused(DeclineReason::ColorTaken);
+                return (StartGameResponse::declined(DeclineReason::ColorTaken), None);
+            }
+            (ColorChoice::White, _) | (ColorChoice::Random, ColorChoice::Black) => PlayerColor::White,
+            (ColorChoice::Black, _) | (ColorChoice::Random, ColorChoice::White) => PlayerColor::Black,
+            (ColorChoice::Random, ColorChoice::Random) => if rand::random() { PlayerColor::White } else { PlayerColor::Black }
+        };
+
+        self.state = HandshakeState::Playing;
+        self.agreed_time_control = request.time_control;
+        self.agreed_start_position = request.fen.as_ref().and_then(|fen| fen.parse().ok());
+        let our_color: Color = their_color.into();
+
+        (StartGameResponse::accepted(their_color), Some(our_color.invert()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(color: ColorChoice, nonce: u64) -> StartGameRequest {
+        StartGameRequest { color, nonce, time_control: None, fen: None }
+    }
+
+    fn proposed_nonce(handshake: &Handshake) -> u64 {
+        match handshake.get_state() {
+            HandshakeState::Proposed(nonce) => *nonce,
+            state => panic!("nothing proposed: {:?}", state)
+        }
+    }
+
+    #[test]
+    fn crossed_proposals_agree_on_one_game() {
+        let mut white = Handshake::new(ColorChoice::White, None, None);
+        let mut black = Handshake::new(ColorChoice::Black, None, None);
+        let white_request = white.propose().unwrap();
+        let black_request = black.propose().unwrap();
+        assert_ne!(white_request.nonce, black_request.nonce);
+
+        let (to_white, black_color) = black.receive(&white_request);
+        let (to_black, white_color) = white.receive(&black_request);
+        let white_color = white.answered(white_request.nonce, Ok(to_white)).or(white_color);
+        let black_color = black.answered(black_request.nonce, Ok(to_black)).or(black_color);
+
+        assert_eq!(white.get_state(), &HandshakeState::Playing);
+        assert_eq!(black.get_state(), &HandshakeState::Playing);
+        assert!(white_color == Some(Color::White));
+        assert!(black_color == Some(Color::Black));
+    }
+
+    #[test]
+    fn higher_nonce_wins() {
+        let mut handshake = Handshake::new(ColorChoice::Random, None, None);
+        handshake.propose().unwrap();
+        let nonce = proposed_nonce(&handshake);
+
+        if nonce < u64::MAX {
+            let (response, color) = handshake.receive(&request(ColorChoice::White, nonce + 1));
+            assert_eq!(response, StartGameResponse::accepted(PlayerColor::White));
+            assert!(color == Some(Color::Black));
+            assert_eq!(handshake.get_state(), &HandshakeState::Playing);
+        }
+    }
+
+    #[test]
+    fn lower_nonce_loses() {
+        let mut handshake = Handshake::new(ColorChoice::Random, None, None);
+        handshake.propose().unwrap();
+        let nonce = proposed_nonce(&handshake);
+
+        if nonce > 0 {
+            let (response, color) = handshake.receive(&request(ColorChoice::White, nonce - 1));
+            assert_eq!(response, StartGameResponse::declined(DeclineReason::Crossed));
+            assert!(color.is_none());
+            assert_eq!(handshake.get_state(), &HandshakeState::Proposed(nonce));
+        }
+    }
+
+    #[test]
+    fn equal_nonces_start_over() {
+        let mut handshake = Handshake::new(ColorChoice::White, None, None);
+        handshake.propose().unwrap();
+        let nonce = proposed_nonce(&handshake);
+
+        let (response, color) = handshake.receive(&request(ColorChoice::Black, nonce));
+        assert_eq!(response, StartGameResponse::declined(DeclineReason::Crossed));
+        assert!(color.is_none());
+        assert_eq!(handshake.get_state(), &HandshakeState::Idle);
+
+        //Our own proposal is answered the same way and we propose again
+        assert!(handshake.answered(nonce, Ok(StartGameResponse::declined(DeclineReason::Crossed))).is_none());
+        assert!(handshake.propose().is_some());
+    }
+
+    #[test]
+    fn color_clash_is_refused_and_retried_with_either_color() {
+        let mut handshake = Handshake::new(ColorChoice::White, None, None);
+        let (response, color) = handshake.receive(&request(ColorChoice::White, 7));
+        assert_eq!(response, StartGameResponse::declined(DeclineReason::ColorTaken));
+        assert!(color.is_none());
+        assert_eq!(handshake.get_state(), &HandshakeState::Refused(DeclineReason::ColorTaken));
+        assert!(handshake.propose().is_none());
+
+        handshake.retry();
+        assert_eq!(handshake.propose().unwrap().color, ColorChoice::Random);
+    }
+
+    #[test]
+    fn opponent_declining_the_color_is_retried_with_either_color() {
+        let mut handshake = Handshake::new(ColorChoice::Black, None, None);
+        let nonce = handshake.propose().unwrap().nonce;
+        handshake.answered(nonce, Ok(StartGameResponse::declined(DeclineReason::ColorTaken)));
+        assert_eq!(handshake.get_state(), &HandshakeState::Declined(DeclineReason::ColorTaken));
+
+        handshake.retry();
+        let retried = handshake.propose().unwrap();
+        assert_eq!(retried.color, ColorChoice::Random);
+
+        let color = handshake.answered(retried.nonce, Ok(StartGameResponse::accepted(PlayerColor::White)));
+        assert!(color == Some(Color::White));
+    }
+
+    #[test]
+    fn proposals_are_declined_during_a_game() {
+        let mut handshake = Handshake::new(ColorChoice::White, None, None);
+        handshake.receive(&request(ColorChoice::Black, 1));
+        let (response, color) = handshake.receive(&request(ColorChoice::Black, 2));
+        assert_eq!(response, StartGameResponse::declined(DeclineReason::InGame));
+        assert!(color.is_none());
+    }
+}
@@ -0,0 +1,263 @@
+use std::io::BufRead;
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
+use schackmotor::{Board, PieceType, Position};
+use crate::{DataHandler, NotatedMove};
+use crate::clock::format_duration;
+use crate::config::Config;
+use crate::fen::Fen;
+use crate::notation;
+use crate::network::NetworkHandler;
+use crate::protocol::DrawAction;
+
+const START_GAME_DELAY: Duration = Duration::from_secs(1);
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+const HELP: &str = "commands:
+    e2e4, e2-e4, e7e8q, e7-e8=Q   make a move
+    board                         print the board
+    moves                         print the moves played so far
+    save                          save the game as PGN
+    fen                           print the position as FEN
+    fen FEN                       start from another position before the game has begun
+    draw                          offer a draw, or withdraw your offer
+    accept                        accept the opponent's draw offer
+    decline                       decline the opponent's draw offer
+    resign                        give up the game, asks for confirmation
+    rematch                       ask for a rematch once the game is over
+    retry                         propose a new game after one was declined, with either color if both wanted the same
+    help                          print this message
+    quit                          leave the game";
+
+//Plays the same network protocol as the GUI, but from the terminal
+pub(crate) fn run(config: &Config) -> Result<(), String> {
+    let data_handler = Arc::new(Mutex::new(DataHandler::new(config.start_position.clone().unwrap_or_else(Fen::standard))));
+    let mut network_handler = NetworkHandler::new(config, data_handler.clone())?;
+    let input = read_stdin_lines();
+    let mut last_proposal = Instant::now();
+    let mut status_text = None;
+    let mut local_color = None;
+    let mut confirming_resign = false;
+    let mut last_tick = Instant::now();
+
+    println!("{}\n", HELP);
+    print_board(&data_handler.lock().unwrap());
+
+    loop {
+        if network_handler.get_local_player_color().is_none() && last_proposal.elapsed() >= START_GAME_DELAY {
+            last_proposal = Instant::now();
+            network_handler.start_game();
+        }
+
+        let game_over = data_handler.lock().unwrap().is_game_over();
+        if network_handler.get_status_text(game_over) != status_text {
+            status_text = network_handler.get_status_text(game_over);
+            if let Some(text) = &status_text {
+                println!("{}", text);
+            }
+        }
+
+        if network_handler.get_local_player_color() != local_color {
+            local_color = network_handler.get_local_player_color();
+            if let Some(color) = local_color {
+                println!("You are playing {}", color);
+            }
+        }
+
+        match input.recv_timeout(POLL_INTERVAL) {
+            Ok(line) => {
+                let line = line.trim();
+                if confirming_resign {
+                    confirming_resign = false;
+                    if line == "yes" || line == "y" {
+                        resign(&data_handler, &network_handler);
+                    }
+                    continue;
+                }
+
+                match line {
+                    "" => {}
+                    "resign" => {
+                        confirming_resign = true;
+                        println!("Resign? (yes/no)");
+                    }
+                    "quit" | "exit" => return Ok(()),
+                    "help" => println!("{}", HELP),
+                    "board" => print_board(&data_handler.lock().unwrap()),
+                    "save" => data_handler.lock().unwrap().save_pgn(network_handler.pgn_headers(), &config.pgn_directory),
+                    "moves" => {
+                        let data_handler = data_handler.lock().unwrap();
+                        for row in notation::numbered_rows(&data_handler.history, &data_handler.start_position) {
+                            println!("{}", row);
+                        }
+                    }
+                    "fen" => println!("{}", data_handler.lock().unwrap().current_fen()),
+                    line if line.starts_with("fen ") => {
+                        let result = line["fen ".len()..].parse::<Fen>().and_then(|start_position| {
+                            network_handler.set_start_position(start_position.clone())?;
+                            data_handler.lock().unwrap().set_start_position(start_position, None);
+                            Ok(())
+                        });
+                        if let Err(e) = result {
+                            println!("{}", e);
+                        }
+                    }
+                    "retry" => network_handler.retry_start_game(),
+                    "rematch" => {
+                        if !data_handler.lock().unwrap().is_game_over() {
+                            println!("The game is not over yet");
+                        } else {
+                            match network_handler.request_rematch() {
+                                Ok(true) => data_handler.lock().unwrap().reset(),
+                                Ok(false) => {}
+                                Err(e) => println!("{}", e)
+                            }
+                        }
+                    }
+                    "draw" | "accept" | "decline" => {
+                        let action = match line {
+                            "draw" if network_handler.get_draw_offers().0 => DrawAction::Withdraw,
+                            "draw" => DrawAction::Offer,
+                            "accept" => DrawAction::Accept,
+                            _ => DrawAction::Decline
+                        };
+                        match network_handler.send_draw_action(action) {
+                            Ok(true) => {
+                                let mut data_handler = data_handler.lock().unwrap();
+                                data_handler.agree_draw();
+                                print_board(&data_handler);
+                            }
+                            Ok(false) => {}
+                            Err(e) => println!("{}", e)
+                        }
+                    }
+                    _ => match NotatedMove::parse(line) {
+                        Ok(mov) => {
+                            if let Err(e) = data_handler.lock().unwrap().take_move(mov, Some(&network_handler)) {
+                                println!("{}", e);
+                            }
+                        }
+                        Err(e) => println!("{}", e)
+                    }
+                }
+            }
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(())
+        }
+
+        let mut data_handler = data_handler.lock().unwrap();
+        if network_handler.get_local_player_color().is_some() {
+            data_handler.tick(last_tick.elapsed());
+        }
+        last_tick = Instant::now();
+
+        if network_handler.record_result(data_handler.is_game_over(), data_handler.winner()) {
+            let (wins, losses) = network_handler.get_score();
+            println!("Score: you {} - {} opponent", wins, losses);
+            data_handler.save_pgn(network_handler.pgn_headers(), &config.pgn_directory);
+        }
+
+        if data_handler.move_made {
+            data_handler.move_made = false;
+            print_board(&data_handler);
+        }
+    }
+}
+
+//The lock is let go before telling the peer, whose listener may be waiting for its own DataHandler
+fn resign(data_handler: &Mutex<DataHandler>, network_handler: &NetworkHandler) {
+    {
+        let mut data_handler = data_handler.lock().unwrap();
+        let local_color = match network_handler.get_local_player_color() {
+            Some(color) if !data_handler.is_game_over() => color,
+            _ => {
+                println!("There is no game to resign");
+                return;
+            }
+        };
+        data_handler.resign(local_color);
+        print_board(&data_handler);
+    }
+
+    if let Err(e) = network_handler.resign() {
+        println!("{}", e);
+    }
+}
+
+fn read_stdin_lines() -> mpsc::Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        let stdin = std::io::stdin();
+        for line in stdin.lock().lines() {
+            match line {
+                Ok(line) => {
+                    if sender.send(line).is_err() {
+                        break;
+                    }
+                }
+                Err(_) => break
+            }
+        }
+    });
+
+    receiver
+}
+
+fn print_board(data_handler: &DataHandler) {
+    println!("{}", board_to_text(&data_handler.board));
+
+    if let Some(clock) = &data_handler.clock {
+        println!("White {}  Black {}", format_duration(clock.remaining(schackmotor::Color::White)),
+                 format_duration(clock.remaining(schackmotor::Color::Black)));
+    }
+
+    if let Some(color) = data_handler.resigned {
+        println!("{} resigned", color);
+        return;
+    }
+
+    if let Some(color) = data_handler.timed_out {
+        println!("{} ran out of time", color);
+        return;
+    }
+
+    match data_handler.gameover {
+        schackmotor::GameState::Normal => println!("{} to move", data_handler.board.get_current_player()),
+        schackmotor::GameState::Check(color) => println!("{} is in check", color),
+        schackmotor::GameState::Checkmate(color) => println!("{} has won", color),
+        schackmotor::GameState::Draw => println!("Draw")
+    }
+}
+
+//White pieces are upper case, black pieces lower case
+pub(crate) fn board_to_text(board: &Board) -> String {
+    let mut text = String::new();
+
+    for y in (1..=8).rev() {
+        text.push_str(&format!("{} ", y));
+        for x in 1..=8 {
+            let symbol = match board.get_piece_at(Position::new(x, y)) {
+                Some(piece) => {
+                    let symbol = match piece.get_type() {
+                        PieceType::King => 'k',
+                        PieceType::Queen => 'q',
+                        PieceType::Rook => 'r',
+                        PieceType::Bishop => 'b',
+                        PieceType::Knight => 'n',
+                        PieceType::Pawn => 'p'
+                    };
+                    if piece.get_color() == schackmotor::Color::White { symbol.to_ascii_uppercase() } else { symbol }
+                }
+                None => '.'
+            };
+            text.push(symbol);
+            text.push(' ');
+        }
+        text.push('\n');
+    }
+    text.push_str("  a b c d e f g h");
+
+    text
+}
@@ -1,4 +1,18 @@
+mod clock;
+mod config;
+mod engine;
+mod fen;
+mod handshake;
+mod headless;
 mod network;
+mod notation;
+mod pgn;
+mod protocol;
+mod replay;
+mod settings;
+mod sprites;
+mod theme;
+mod uci;
 
 use ggez::event;
 use ggez::graphics::{self, DrawParam, DrawMode};
@@ -8,19 +22,41 @@ use std::{env, fmt};
 use std::path;
 use schackmotor::{Board, PieceType, Position};
 use crate::network::NetworkHandler;
+use crate::config::Config;
+use crate::engine::Engine;
+use crate::fen::Fen;
+use crate::protocol::{ClockState, ColorChoice, DrawAction, MoveRequest, MoveResponse, TimeControl};
+use crate::clock::{Clock, format_duration};
+use crate::notation::PlayedMove;
+use crate::pgn::PgnHeaders;
+use crate::settings::Settings;
+use crate::sprites::PieceSprites;
+use crate::theme::{BoardTheme, MenuChange, PieceSet, ThemeMenu};
+use crate::uci::{Analysis, UciEngine};
 use std::sync::{Mutex, Arc};
-use std::ops::Deref;
 use std::fmt::{Formatter};
-use std::io::BufRead;
+use clipboard::{ClipboardContext, ClipboardProvider};
 
 const GRID_SIZE: (i16, i16) = (8, 8);
+//Sizes in the default window, everything is scaled by Layout when the window is resized
 const GRID_CELL_SIZE: (i16, i16) = (45, 45);
 
-const SCREEN_SIZE: (f32, f32) = (
+const BOARD_SIZE: (f32, f32) = (
     GRID_SIZE.0 as f32 * GRID_CELL_SIZE.0 as f32,
     GRID_SIZE.1 as f32 * GRID_CELL_SIZE.1 as f32,
 );
 
+const SIDE_PANEL_WIDTH: f32 = 120.0;
+
+const SCREEN_SIZE: (f32, f32) = (BOARD_SIZE.0 + SIDE_PANEL_WIDTH, BOARD_SIZE.1);
+
+const HISTORY_ROW_HEIGHT: f32 = 18.0;
+const HISTORY_COLUMN_WIDTH: f32 = 44.0;
+
+const RETURN_DURATION: std::time::Duration = std::time::Duration::from_millis(150);
+
+const MAX_CLOCK_DRIFT: std::time::Duration = std::time::Duration::from_millis(1000);
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 struct GridPosition {
     x: i32,
@@ -28,34 +64,79 @@ struct GridPosition {
 }
 
 impl GridPosition {
-    fn from(pos: schackmotor::Position) -> Self {
-        GridPosition {
-            x: pos.get_x() as i32 - 1,
-            y: (8 - pos.get_y()) as i32,
+    //The grid counts from the top left corner of the board, which is a8, or h1 when the board is flipped
+    fn from_position(pos: schackmotor::Position, flipped: bool) -> Self {
+        if flipped {
+            GridPosition {
+                x: (8 - pos.get_x()) as i32,
+                y: pos.get_y() as i32 - 1,
+            }
+        } else {
+            GridPosition {
+                x: pos.get_x() as i32 - 1,
+                y: (8 - pos.get_y()) as i32,
+            }
         }
     }
-}
 
-impl From<GridPosition> for graphics::Rect {
-    fn from(pos: GridPosition) -> Self {
-        graphics::Rect::new_i32(
-            pos.x * GRID_CELL_SIZE.0 as i32,
-            pos.y * GRID_CELL_SIZE.1 as i32,
-            GRID_CELL_SIZE.0 as i32,
-            GRID_CELL_SIZE.1 as i32,
-        )
+    fn to_position(self, flipped: bool) -> schackmotor::Position {
+        if flipped {
+            schackmotor::Position::new((8 - self.x) as u8, (self.y + 1) as u8)
+        } else {
+            schackmotor::Position::new((self.x + 1) as u8, (8 - self.y) as u8)
+        }
     }
 }
 
-impl From<GridPosition> for ggez::mint::Point2<f32> {
-    fn from(pos: GridPosition) -> Self {
-        ggez::mint::Point2 { x: (pos.x * GRID_CELL_SIZE.0 as i32) as f32, y: (pos.y * GRID_CELL_SIZE.1 as i32) as f32 }
-    }
+//Where the board and side panel are drawn in a window of any size. Everything keeps the proportions of
+//the default window, scaled to fit and centered, so the board stays square.
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct Layout {
+    cell_size: f32,
+    left: f32,
+    top: f32,
 }
 
-impl From<GridPosition> for schackmotor::Position {
-    fn from(pos: GridPosition) -> Self {
-        schackmotor::Position::new((pos.x + 1) as u8, (8 - pos.y) as u8)
+impl Layout {
+    fn new(width: f32, height: f32) -> Self {
+        let scale = (width / SCREEN_SIZE.0).min(height / SCREEN_SIZE.1);
+        Layout {
+            cell_size: GRID_CELL_SIZE.0 as f32 * scale,
+            left: (width - SCREEN_SIZE.0 * scale) / 2.0,
+            top: (height - SCREEN_SIZE.1 * scale) / 2.0,
+        }
+    }
+
+    //How much larger everything is drawn than in the default window
+    fn scale(&self) -> f32 {
+        self.cell_size / GRID_CELL_SIZE.0 as f32
+    }
+
+    fn board_size(&self) -> f32 {
+        self.cell_size * GRID_SIZE.0 as f32
+    }
+
+    //The left edge of the side panel
+    fn panel_left(&self) -> f32 {
+        self.left + self.board_size()
+    }
+
+    fn square(&self, pos: GridPosition) -> graphics::Rect {
+        graphics::Rect::new(self.left + pos.x as f32 * self.cell_size, self.top + pos.y as f32 * self.cell_size,
+                            self.cell_size, self.cell_size)
+    }
+
+    fn corner(&self, pos: GridPosition) -> ggez::mint::Point2<f32> {
+        ggez::mint::Point2 { x: self.left + pos.x as f32 * self.cell_size, y: self.top + pos.y as f32 * self.cell_size }
+    }
+
+    //The square under a point of the window, if it is on the board
+    fn grid_at(&self, x: f32, y: f32) -> Option<GridPosition> {
+        let (x, y) = ((x - self.left) / self.cell_size, (y - self.top) / self.cell_size);
+        if x < 0.0 || y < 0.0 || x >= GRID_SIZE.0 as f32 || y >= GRID_SIZE.1 as f32 {
+            return None;
+        }
+        Some(GridPosition { x: x.floor() as i32, y: y.floor() as i32 })
     }
 }
 
@@ -65,8 +146,8 @@ struct Tile {
 }
 
 impl Tile {
-    fn draw(&self, ctx: &mut Context) -> GameResult<()> {
-        let rectangle = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), self.position.into(), self.color)?;
+    fn draw(&self, ctx: &mut Context, layout: &Layout) -> GameResult<()> {
+        let rectangle = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), layout.square(self.position), self.color)?;
         graphics::draw(ctx, &rectangle, (ggez::mint::Point2 { x: 0.0, y: 0.0 }, ))
     }
 }
@@ -100,69 +181,247 @@ impl NotatedMove {
         }
     }
 
-    fn jsonify(&self) -> String {
-        match &self.promotes_to {
-            Some(s) => {
-                format!("{0}\"start_square\":\"{2}\",\"end_square\":\"{3}\",\"promotes_to\":\"{4}\"{1}", "{", "}", self.start_position, self.end_position, s)
-            }
-            None => {
-                format!("{0}\"start_square\":\"{2}\",\"end_square\":\"{3}\"{1}", "{", "}", self.start_position, self.end_position)
-            }
+    //Accepts both "e7-e8=Q" and the more compact "e7e8q"
+    fn parse(text: &str) -> Result<Self, String> {
+        let compact: String = text.trim().chars().filter(|c| *c != '-' && *c != '=').collect();
+
+        if (compact.len() != 4 && compact.len() != 5) || !compact.is_ascii() {
+            return Err(format!("Can't read move \"{}\"", text.trim()));
+        }
+
+        let start_position = compact[0..2].to_lowercase();
+        let end_position = compact[2..4].to_lowercase();
+        if !protocol::is_square(&start_position) || !protocol::is_square(&end_position) {
+            return Err(format!("Can't read move \"{}\"", text.trim()));
         }
+
+        let promotes_to = match compact[4..].to_uppercase().as_str() {
+            "" => None,
+            piece @ "Q" | piece @ "R" | piece @ "B" | piece @ "N" => Some(piece.to_string()),
+            piece => return Err(format!("Can't promote to \"{}\"", piece))
+        };
+
+        Ok(NotatedMove::new(start_position, end_position, promotes_to))
     }
 }
 
 struct GraphicsPiece {
-    sprite: graphics::Image,
-    position: GridPosition,
+    sprite: Option<graphics::Image>, //None when the piece set has no image for the piece
+    color: schackmotor::Color,
+    piece_type: PieceType,
+    position: Position,
 }
 
 impl GraphicsPiece {
-    fn draw(&self, ctx: &mut Context) -> GameResult<()> {
-        graphics::draw(ctx, &self.sprite, DrawParam::default().dest(self.position)
-            .scale(ggez::mint::Vector2 { x: GRID_CELL_SIZE.0 as f32 / 45.0, y: GRID_CELL_SIZE.1 as f32 / 45.0 }))
+    fn draw(&self, ctx: &mut Context, layout: &Layout, flipped: bool) -> GameResult<()> {
+        self.draw_at(ctx, layout, layout.corner(GridPosition::from_position(self.position, flipped)), 1.0)
+    }
+
+    //Sprites are drawn to fill a square whatever size the images are, with their top left corner at `dest`
+    fn draw_at(&self, ctx: &mut Context, layout: &Layout, dest: ggez::mint::Point2<f32>, alpha: f32) -> GameResult<()> {
+        let sprite = match &self.sprite {
+            Some(sprite) => sprite,
+            None => return self.draw_letter(ctx, layout, dest, alpha)
+        };
+
+        let scale = layout.cell_size / sprite.width() as f32;
+        graphics::draw(ctx, sprite, DrawParam::default().dest(dest).color([1.0, 1.0, 1.0, alpha].into())
+            .scale(ggez::mint::Vector2 { x: scale, y: scale }))
+    }
+
+    //A disc in the color of the piece with its letter on it, for pieces without an image
+    fn draw_letter(&self, ctx: &mut Context, layout: &Layout, dest: ggez::mint::Point2<f32>, alpha: f32) -> GameResult<()> {
+        let (fill, ink) = if self.color == schackmotor::Color::White {
+            ([1.0, 1.0, 1.0, alpha], [0.0, 0.0, 0.0, alpha])
+        } else {
+            ([0.1, 0.1, 0.1, alpha], [1.0, 1.0, 1.0, alpha])
+        };
+        let center = ggez::mint::Point2 { x: dest.x + layout.cell_size / 2.0, y: dest.y + layout.cell_size / 2.0 };
+
+        let disc = graphics::Mesh::new_circle(ctx, DrawMode::fill(), center, layout.cell_size * 0.4, 0.5, fill.into())?;
+        graphics::draw(ctx, &disc, DrawParam::default())?;
+
+        let letter = match notation::piece_letter(self.piece_type) {
+            "" => "P",
+            letter => letter
+        };
+        let size = layout.cell_size * 0.5;
+        let gg_text = graphics::Text::new(graphics::TextFragment::from(letter).scale(graphics::Scale { x: size, y: size }));
+        let gg_dimensions = gg_text.dimensions(ctx);
+        graphics::draw(ctx, &gg_text, DrawParam::default().color(ink.into())
+            .dest(ggez::mint::Point2 { x: center.x - gg_dimensions.0 as f32 / 2.0, y: center.y - gg_dimensions.1 as f32 / 2.0 }))
+    }
+
+    fn is_same(&self, other: &GraphicsPiece) -> bool {
+        self.position == other.position && self.color == other.color && self.piece_type == other.piece_type
     }
 }
 
+//The pieces of the last move sliding to their squares, two of them when castling, and a captured piece fading out
+struct MoveAnimation {
+    moving: Vec<(Position, Position)>, //the squares each piece slides from and to
+    captured: Vec<GraphicsPiece>,
+    elapsed: std::time::Duration,
+}
+
 struct MarkedTile {
-    position: GridPosition
+    position: Position
 }
 
 impl MarkedTile {
-    fn draw(&self, ctx: &mut Context) -> GameResult<()> {
-        let circle = graphics::Mesh::new_circle
-            (ctx, DrawMode::fill(), graphics::mint::Point2 { x: 22.5, y: 22.5 }, 7.5, 1.0, [0.5, 0.5, 0.5, 0.5].into())?;
-        graphics::draw(ctx, &circle, DrawParam::default().dest(self.position))
+    fn draw(&self, ctx: &mut Context, layout: &Layout, flipped: bool) -> GameResult<()> {
+        let circle = graphics::Mesh::new_circle(ctx, DrawMode::fill(),
+                                                graphics::mint::Point2 { x: layout.cell_size / 2.0, y: layout.cell_size / 2.0 },
+                                                layout.cell_size / 6.0, 1.0, [0.5, 0.5, 0.5, 0.5].into())?;
+        graphics::draw(ctx, &circle, DrawParam::default().dest(layout.corner(GridPosition::from_position(self.position, flipped))))
     }
 
     fn new(position: Position) -> MarkedTile {
-        MarkedTile { position: GridPosition::from(position) }
+        MarkedTile { position }
     }
 }
 
+//A message for the peer, sent by the caller only after it has let go of the DataHandler. The peer's
+//listener may need its own DataHandler to answer, which its window could be holding while it waits on us.
+enum PeerRequest {
+    Draw(DrawAction),
+    Resign
+}
+
 struct InputHandler {
     clicked_tile: Option<Position>,
     clicked_tile_2: Option<Position>,
+    pressed_tile: Option<Position>, //the square of the piece being dragged while the mouse button is held
+    confirming_resign: bool,
+    confirming_draw: bool, //only used when both players share this window
+    local_color: Option<schackmotor::Color>, //the color played here when there is no network peer, None if both are
+    always_queen: bool,
+    confirm_moves: bool,
+    pending_move: Option<NotatedMove>, //a move waiting to be confirmed
 }
 
 impl InputHandler {
     fn new() -> Self {
         InputHandler {
             clicked_tile: None,
-            clicked_tile_2: None
+            clicked_tile_2: None,
+            pressed_tile: None,
+            confirming_resign: false,
+            confirming_draw: false,
+            local_color: None,
+            always_queen: false,
+            confirm_moves: false,
+            pending_move: None
+        }
+    }
+
+    fn awaiting_promotion(&self) -> bool {
+        self.clicked_tile_2.is_some()
+    }
+
+    fn get_prompt_text(&self) -> Option<String> {
+        if let Some(mov) = &self.pending_move {
+            Some(format!("Play {}? (Y/N)", mov))
+        } else if self.confirming_resign {
+            Some("Resign? (Y/N)".to_string())
+        } else if self.confirming_draw {
+            Some("Agree to a draw? (Y/N)".to_string())
+        } else {
+            None
         }
     }
 
     fn reset_clicked_squares(&mut self) {
         self.clicked_tile = None;
         self.clicked_tile_2 = None;
+        self.pending_move = None;
+    }
+
+    fn opponents_turn(&self, data_handler: &DataHandler, network_handler: Option<&NetworkHandler>) -> bool {
+        network_handler.is_none() && self.local_color.is_some_and(|color| color != data_handler.board.get_current_player())
+    }
+
+    //Picks up a piece of the player to move, it follows the cursor until the button is released
+    fn pressed_at(&mut self, button: MouseButton, x: f32, y: f32,
+                  data_handler: &DataHandler, graphics_handler: &mut GraphicsHandler, network_handler: Option<&NetworkHandler>) {
+        if button != MouseButton::Left || self.clicked_tile_2.is_some() || self.pending_move.is_some()
+            || self.opponents_turn(data_handler, network_handler) {
+            return;
+        }
+
+        let pressed_position = match graphics_handler.layout.grid_at(x, y) {
+            Some(grid) => grid.to_position(graphics_handler.flipped),
+            None => return
+        };
+
+        if data_handler.can_move_piece_at_position(pressed_position) {
+            if let Some(moves) = data_handler.moves_from_position(pressed_position) {
+                self.clicked_tile = Some(pressed_position);
+                self.pressed_tile = Some(pressed_position);
+                graphics_handler.clear_marks();
+                for mov in moves {
+                    graphics_handler.add_marked_tile(mov.0);
+                }
+                graphics_handler.start_drag(pressed_position, x, y);
+            }
+        }
     }
 
-    fn clicked_at(&mut self, ctx: &mut Context, button: MouseButton, x: f32, y: f32,
-                  data_handler: &mut DataHandler, graphics_handler: &mut GraphicsHandler, network_handler: &NetworkHandler) {
-        if button == MouseButton::Left && self.clicked_tile_2.is_none() {
-            let clicked_position: schackmotor::Position = GridPosition { x: (x / GRID_CELL_SIZE.0 as f32).floor() as i32,
-                y: (y / GRID_CELL_SIZE.1 as f32).floor() as i32 }.into();
+    //Plays a dragged piece to the square it was dropped on if it can go there, otherwise it slides back.
+    //Dropping it where it was picked up leaves it selected, so it can be moved with a second click.
+    fn dropped_at(&mut self, pressed_tile: Position, x: f32, y: f32,
+                  data_handler: &mut DataHandler, graphics_handler: &mut GraphicsHandler, network_handler: Option<&NetworkHandler>) {
+        let dropped_position = graphics_handler.layout.grid_at(x, y).map(|grid| grid.to_position(graphics_handler.flipped));
+        let (moves, promotes) = dropped_position
+            .map_or((false, false), |position| data_handler.piece_at_position_can_move_to(pressed_tile, position));
+
+        match dropped_position {
+            Some(position) if position == pressed_tile => graphics_handler.end_drag(),
+            Some(position) if moves => {
+                graphics_handler.end_drag();
+                if promotes {
+                    self.promote(position, data_handler, graphics_handler, network_handler);
+                } else {
+                    self.forward_move(NotatedMove::new(pressed_tile.to_string(), position.to_string(), None),
+                                      data_handler, graphics_handler, network_handler);
+                    //The piece is already where it was dropped
+                    graphics_handler.skip_animation();
+                }
+            }
+            _ => {
+                graphics_handler.return_dragged_piece();
+                self.clicked_tile = None;
+                graphics_handler.clear_marks();
+            }
+        }
+    }
+
+    fn clicked_at(&mut self, button: MouseButton, x: f32, y: f32,
+                  data_handler: &mut DataHandler, graphics_handler: &mut GraphicsHandler, network_handler: Option<&NetworkHandler>) {
+        if self.pending_move.is_some() {
+            return;
+        }
+
+        if button == MouseButton::Left {
+            if let Some(pressed_tile) = self.pressed_tile.take() {
+                self.dropped_at(pressed_tile, x, y, data_handler, graphics_handler, network_handler);
+                return;
+            }
+        }
+
+        if button == MouseButton::Left && self.clicked_tile_2.is_some() {
+            match graphics_handler.promotion_choice_at(x, y) {
+                Some(piece_type) => self.choose_promotion(piece_type, data_handler, graphics_handler, network_handler),
+                None => self.cancel_promotion(graphics_handler)
+            }
+            return;
+        }
+
+        let opponents_turn = self.opponents_turn(data_handler, network_handler);
+
+        let clicked_grid = graphics_handler.layout.grid_at(x, y);
+        if button == MouseButton::Left && self.clicked_tile_2.is_none() && !opponents_turn && clicked_grid.is_some() {
+            let clicked_position: schackmotor::Position = clicked_grid.unwrap().to_position(graphics_handler.flipped);
 
             if self.clicked_tile.is_none() {
                 if let Some(moves) = data_handler.moves_from_position(clicked_position){
@@ -186,9 +445,9 @@ impl InputHandler {
 
                 if moves {
                     if promotes {
-                        self.clicked_tile_2 = Some(clicked_position);
+                        self.promote(clicked_position, data_handler, graphics_handler, network_handler);
                     } else {
-                        self.forward_move(ctx, NotatedMove::new(
+                        self.forward_move(NotatedMove::new(
                             self.clicked_tile.unwrap().to_string(), clicked_position.to_string(), None)
                                           , data_handler, graphics_handler, network_handler);
 
@@ -201,53 +460,166 @@ impl InputHandler {
         }
     }
 
-    fn key_pressed(&mut self, ctx: &mut Context, keycode: ggez::event::KeyCode,
-                   data_handler: &mut DataHandler, graphics_handler: &mut GraphicsHandler, network_handler: &NetworkHandler) {
-        if self.clicked_tile_2.is_some() {
+    //Asks which piece the pawn moving from the selected square to `end_position` becomes, unless it always becomes a queen
+    fn promote(&mut self, end_position: Position,
+               data_handler: &mut DataHandler, graphics_handler: &mut GraphicsHandler, network_handler: Option<&NetworkHandler>) {
+        self.clicked_tile_2 = Some(end_position);
+        if self.always_queen {
+            self.choose_promotion(PieceType::Queen, data_handler, graphics_handler, network_handler);
+        } else {
+            graphics_handler.show_promotion_choices(end_position, data_handler.board.get_current_player());
+        }
+    }
+
+    fn choose_promotion(&mut self, piece_type: PieceType,
+                        data_handler: &mut DataHandler, graphics_handler: &mut GraphicsHandler, network_handler: Option<&NetworkHandler>) {
+        if let (Some(start_position), Some(end_position)) = (self.clicked_tile, self.clicked_tile_2) {
+            self.forward_move(NotatedMove::new(start_position.to_string(), end_position.to_string(),
+                                                    Some(notation::piece_letter(piece_type).to_string())),
+                              data_handler, graphics_handler, network_handler);
+        }
+    }
+
+    //Puts the pawn back, the move can be made again or another one chosen
+    fn cancel_promotion(&mut self, graphics_handler: &mut GraphicsHandler) {
+        self.reset_clicked_squares();
+        graphics_handler.hide_promotion_choices();
+        graphics_handler.clear_marks();
+    }
+
+    fn key_pressed(&mut self, keycode: ggez::event::KeyCode, data_handler: &mut DataHandler,
+                   graphics_handler: &mut GraphicsHandler, network_handler: Option<&NetworkHandler>) -> Option<PeerRequest> {
+        if let Some(mov) = self.pending_move.take() {
+            if keycode == KeyCode::Y {
+                self.play_move(mov, data_handler, graphics_handler, network_handler);
+            } else {
+                self.reset_clicked_squares();
+                graphics_handler.clear_marks();
+            }
+        } else if self.clicked_tile_2.is_some() {
             match keycode {
-                KeyCode::Q => {
-                    self.forward_move(ctx, NotatedMove::new(
-                        self.clicked_tile.unwrap().to_string(), self.clicked_tile_2.unwrap().to_string(), Some("Q".to_string())),
-                                      data_handler, graphics_handler, network_handler);
-                }
-                KeyCode::R => {
-                    self.forward_move(ctx, NotatedMove::new(
-                        self.clicked_tile.unwrap().to_string(), self.clicked_tile_2.unwrap().to_string(), Some("R".to_string())),
-                                      data_handler, graphics_handler, network_handler);
-                }
-                KeyCode::B => {
-                    self.forward_move(ctx, NotatedMove::new(
-                        self.clicked_tile.unwrap().to_string(), self.clicked_tile_2.unwrap().to_string(), Some("B".to_string())),
-                                      data_handler, graphics_handler, network_handler);
-                }
-                KeyCode::N => {
-                    self.forward_move(ctx, NotatedMove::new(
-                        self.clicked_tile.unwrap().to_string(), self.clicked_tile_2.unwrap().to_string(), Some("N".to_string())),
-                                      data_handler, graphics_handler, network_handler);
-                }
+                KeyCode::Q => self.choose_promotion(PieceType::Queen, data_handler, graphics_handler, network_handler),
+                KeyCode::R => self.choose_promotion(PieceType::Rook, data_handler, graphics_handler, network_handler),
+                KeyCode::B => self.choose_promotion(PieceType::Bishop, data_handler, graphics_handler, network_handler),
+                KeyCode::N => self.choose_promotion(PieceType::Knight, data_handler, graphics_handler, network_handler),
+                KeyCode::Escape => self.cancel_promotion(graphics_handler),
                 _ => {}
             }
+        } else if self.confirming_resign || (keycode == KeyCode::F && !self.confirming_draw) {
+            return self.resign_key_pressed(keycode, data_handler, network_handler);
+        } else if let Some(network_handler) = network_handler {
+            return self.draw_offer_key_pressed(keycode, data_handler, network_handler).map(PeerRequest::Draw);
+        } else if self.local_color.is_none() {
+            self.local_draw_key_pressed(keycode, data_handler);
+        }
+        None
+    }
+
+    //F asks to resign, Y confirms and anything else cancels. When both players share this window
+    //it is the player to move who resigns.
+    fn resign_key_pressed(&mut self, keycode: ggez::event::KeyCode, data_handler: &mut DataHandler,
+                          network_handler: Option<&NetworkHandler>) -> Option<PeerRequest> {
+        let resigning_color = match network_handler {
+            Some(network_handler) => network_handler.get_local_player_color(),
+            None => Some(self.local_color.unwrap_or_else(|| data_handler.board.get_current_player()))
+        };
+
+        let local_color = match resigning_color {
+            Some(color) if !data_handler.is_game_over() => color,
+            _ => {
+                self.confirming_resign = false;
+                return None;
+            }
+        };
+
+        if !self.confirming_resign {
+            self.confirming_resign = true;
+            return None;
+        }
+
+        self.confirming_resign = false;
+        if keycode != KeyCode::Y {
+            return None;
+        }
+
+        data_handler.resign(local_color);
+        network_handler.map(|_| PeerRequest::Resign)
+    }
+
+    //D offers or withdraws a draw, Y and N answer the opponent's offer
+    fn draw_offer_key_pressed(&mut self, keycode: ggez::event::KeyCode, data_handler: &DataHandler, network_handler: &NetworkHandler) -> Option<DrawAction> {
+        if data_handler.is_game_over() || network_handler.get_local_player_color().is_none() {
+            return None;
+        }
+
+        let (offered, opponent_offered) = network_handler.get_draw_offers();
+        match keycode {
+            KeyCode::D if offered => Some(DrawAction::Withdraw),
+            KeyCode::D => Some(DrawAction::Offer),
+            KeyCode::Y if opponent_offered => Some(DrawAction::Accept),
+            KeyCode::N if opponent_offered => Some(DrawAction::Decline),
+            _ => None
+        }
+    }
+
+    //Both players sit at the same window, so D asks for a draw and Y from the other player agrees to it
+    fn local_draw_key_pressed(&mut self, keycode: ggez::event::KeyCode, data_handler: &mut DataHandler) {
+        if data_handler.is_game_over() {
+            self.confirming_draw = false;
+            return;
         }
+
+        if !self.confirming_draw {
+            self.confirming_draw = keycode == KeyCode::D;
+            return;
+        }
+
+        self.confirming_draw = false;
+        if keycode == KeyCode::Y {
+            data_handler.agree_draw();
+        }
+    }
+
+    //Plays the move, or holds it until Y confirms it when moves have to be confirmed
+    fn forward_move(&mut self, mov: NotatedMove, data_handler: &mut DataHandler, graphics_handler: &mut GraphicsHandler, network_handler: Option<&NetworkHandler>) {
+        if self.confirm_moves {
+            self.pending_move = Some(mov);
+            graphics_handler.hide_promotion_choices();
+            return;
+        }
+        self.play_move(mov, data_handler, graphics_handler, network_handler);
     }
 
-    fn forward_move(&mut self, ctx: &mut Context, mov: NotatedMove, data_handler: &mut DataHandler, graphics_handler: &mut GraphicsHandler, network_handler: &NetworkHandler) {
-        data_handler.take_move(mov, network_handler);
-        graphics_handler.update_board(data_handler, ctx);
+    fn play_move(&mut self, mov: NotatedMove, data_handler: &mut DataHandler, graphics_handler: &mut GraphicsHandler, network_handler: Option<&NetworkHandler>) {
+        if let Err(e) = data_handler.take_move(mov, network_handler) {
+            println!("{}", e);
+        }
+        graphics_handler.update_board(data_handler);
         self.reset_clicked_squares();
     }
 }
 
 struct DataHandler {
     board: Board,
+    start_position: Fen,
     gameover: schackmotor::GameState,
+    resigned: Option<schackmotor::Color>,
+    clock: Option<Clock>,
+    timed_out: Option<schackmotor::Color>,
+    history: Vec<PlayedMove>,
     move_made: bool
 }
 
 impl DataHandler {
-    fn new(board: Board) -> Self {
+    fn new(start_position: Fen) -> Self {
         DataHandler {
-            board,
+            board: start_position.to_board(),
+            start_position,
             gameover: schackmotor::GameState::Normal,
+            resigned: None,
+            clock: None,
+            timed_out: None,
+            history: Vec::new(),
             move_made: false
         }
     }
@@ -256,34 +628,171 @@ impl DataHandler {
         self.gameover = self.board.get_game_state();
     }
 
-    fn take_move<T: Deref<Target = NetworkHandler>>(&mut self, mov: NotatedMove, network_handler: T) -> Result<(), String> {
+    fn take_move(&mut self, mov: NotatedMove, network_handler: Option<&NetworkHandler>) -> Result<(), String> {
+        let network_handler = match network_handler {
+            Some(network_handler) => network_handler,
+            None => {
+                //Both colors are played from this window, so whoever is to move may move
+                let color = self.board.get_current_player();
+                return self.receive_move(mov, color);
+            }
+        };
+
         if network_handler.get_local_player_color().is_none() {
             return Err("No opponent".to_string());
         }
 
-        self.receive_move(mov.clone(), network_handler.get_local_player_color().unwrap().invert())?;
+        let previous_board = self.board.clone();
+        let previous_clock = self.clock.clone();
+        self.receive_move(mov.clone(), network_handler.get_local_player_color().unwrap())?;
 
-        println!("{}\n{}", mov.jsonify(), network_handler.get_target_address());
+        println!("{}\n{}", mov, network_handler.get_target_address());
 
         //Transmit the move to the other client
-        network_handler.send(format!("http://{}/move", network_handler.get_target_address()).as_str(), mov.jsonify());
+        let mut request = MoveRequest::from(&mov);
+        request.clock = self.clock.as_ref().map(|clock| clock.state());
+        let result = network_handler.send::<_, MoveResponse>("/move", &request).and_then(|response| {
+            if response.valid_move { Ok(()) } else { Err("Opponent rejected the move".to_string()) }
+        });
+
+        if result.is_ok() {
+            network_handler.clear_draw_offers();
+        } else {
+            //Take the move back so both boards still agree
+            self.board = previous_board;
+            self.clock = previous_clock;
+            self.history.pop();
+            self.update_game_state();
+            self.move_made = true;
+        }
 
-        Ok(())
+        result
+    }
+
+    fn is_game_over(&self) -> bool {
+        if self.resigned.is_some() || self.timed_out.is_some() {
+            return true;
+        }
+
+        match self.gameover {
+            schackmotor::GameState::Checkmate(_) | schackmotor::GameState::Draw => true,
+            schackmotor::GameState::Normal | schackmotor::GameState::Check(_) => false
+        }
+    }
+
+    fn winner(&self) -> Option<schackmotor::Color> {
+        match (self.resigned, &self.gameover) {
+            (Some(color), _) => Some(color.invert()),
+            (None, schackmotor::GameState::Checkmate(color)) => Some(*color),
+            _ => self.timed_out.map(|color| color.invert())
+        }
+    }
+
+    fn to_pgn(&self, mut headers: PgnHeaders) -> String {
+        headers.result = pgn::result_tag(self.is_game_over(), self.winner()).to_string();
+        if !self.start_position.is_standard() {
+            headers.start_position = Some(self.start_position.clone());
+        }
+        pgn::write_pgn(&headers, &self.history)
+    }
+
+    fn current_fen(&self) -> Fen {
+        Fen::from_game(&self.start_position, &self.board, &self.history)
+    }
+
+    //Whether the first move in the history was Black's, so the history starts in the second column
+    fn history_offset(&self) -> usize {
+        if self.start_position.get_to_move() == schackmotor::Color::Black { 1 } else { 0 }
+    }
+
+    fn save_pgn(&self, headers: PgnHeaders, directory: &path::Path) {
+        match pgn::save_pgn(directory, &self.to_pgn(headers)) {
+            Ok(path) => println!("Saved game to {}", path.display()),
+            Err(e) => println!("{}", e)
+        }
+    }
+
+    //Sets up the board of a new game from the given position, with clocks if there is a time control
+    fn set_start_position(&mut self, start_position: Fen, time_control: Option<TimeControl>) {
+        let first_to_move = start_position.get_to_move();
+        self.start_position = start_position;
+        self.clock = time_control.map(|time_control| Clock::new(time_control, first_to_move));
+        self.reset();
+    }
+
+    //Runs down the clock of the player to move and ends the game if their flag falls
+    fn tick(&mut self, elapsed: std::time::Duration) {
+        if self.is_game_over() {
+            return;
+        }
+
+        if let Some(clock) = &mut self.clock {
+            if clock.tick(elapsed) {
+                self.timed_out = Some(clock.get_to_move());
+                self.move_made = true;
+            }
+        }
     }
 
-    fn receive_move(&mut self, mov: NotatedMove, this_players_color: schackmotor::Color) -> Result<(), String> {
-        if this_players_color != self.board.get_current_player() {
+    fn sync_clock(&mut self, state: &ClockState) {
+        if let Some(clock) = &mut self.clock {
+            let drift = clock.sync(state);
+            if drift > MAX_CLOCK_DRIFT {
+                println!("Clock drifted {} ms from the opponent's", drift.as_millis());
+            }
+        }
+    }
+
+    fn resign(&mut self, color: schackmotor::Color) {
+        if !self.is_game_over() {
+            self.resigned = Some(color);
+            self.move_made = true;
+        }
+    }
+
+    fn agree_draw(&mut self) {
+        self.gameover = schackmotor::GameState::Draw;
+    }
+
+    fn reset(&mut self) {
+        self.board = self.start_position.to_board();
+        self.update_game_state();
+        self.resigned = None;
+        self.timed_out = None;
+        self.history.clear();
+        if let Some(clock) = &mut self.clock {
+            clock.reset();
+        }
+        self.move_made = true;
+    }
+
+    //Plays a move of `moving_color`, which has to be the player to move
+    fn receive_move(&mut self, mov: NotatedMove, moving_color: schackmotor::Color) -> Result<(), String> {
+        if self.is_game_over() {
+            return Err("The game is over".to_string());
+        }
+
+        if moving_color != self.board.get_current_player() {
             return Err("Can't play a piece of the opponents color".to_string());
         }
 
+        let san = notation::to_san(&self.board, &mov).unwrap_or_else(|_| mov.to_string());
+
         self.board.take_move(mov.to_string())?;
         self.update_game_state();
+        self.history.push(PlayedMove { san: san + notation::check_suffix(&self.gameover), notated: mov });
+        if let Some(clock) = &mut self.clock {
+            clock.moved();
+        }
         self.move_made = true;
 
         Ok(())
     }
 
     fn moves_from_position(&self, position: schackmotor::Position) -> Option<Vec<(Position, bool)>> {
+        if self.is_game_over() {
+            return None;
+        }
         self.board.get_possible_moves_from_position(position)
     }
 
@@ -307,94 +816,263 @@ impl DataHandler {
 }
 
 struct GraphicsHandler {
-    sprites: Vec<((schackmotor::Color, schackmotor::PieceType), String)>,
+    sprites: PieceSprites,
+    board_theme: BoardTheme,
     tiles: Vec<Tile>,
     graphics_pieces: Vec<GraphicsPiece>,
     marks: Vec<MarkedTile>,
+    history_scroll: usize, //number of rows the move history is scrolled up from the latest move
+    shown_moves: Option<usize>, //how many moves of the history are on the board if not all of them, used when replaying
+    flipped: bool, //whether Black is at the bottom of the board
+    show_coordinates: bool,
+    layout: Layout,
+    animation: Option<MoveAnimation>,
+    animation_duration: std::time::Duration,
+    promotion_choices: Vec<GraphicsPiece>, //the pieces a pawn can become, drawn over the board while the player picks one
+    dragged: Option<(Position, ggez::mint::Point2<f32>)>, //the square of the piece following the cursor, and the cursor
+    returning: Option<(Position, ggez::mint::Point2<f32>, std::time::Instant)>, //a dropped piece sliding back to its square from where it was dropped
 }
 
 impl GraphicsHandler {
-    fn new(data_handler: &DataHandler, ctx: &mut Context) -> Self {
+    fn new(data_handler: &DataHandler, animation_duration: std::time::Duration, board_theme: &BoardTheme, piece_set: &PieceSet,
+           ctx: &mut Context) -> Self {
         let mut out = GraphicsHandler {
-            sprites: GraphicsHandler::load_sprites(),
+            sprites: PieceSprites::load(ctx, &piece_set.directory),
+            board_theme: board_theme.clone(),
             tiles: Vec::new(),
             graphics_pieces: Vec::new(),
-            marks: Vec::new()
+            marks: Vec::new(),
+            history_scroll: 0,
+            shown_moves: None,
+            flipped: false,
+            show_coordinates: true,
+            layout: Layout::new(SCREEN_SIZE.0, SCREEN_SIZE.1),
+            animation: None,
+            animation_duration,
+            promotion_choices: Vec::new(),
+            dragged: None,
+            returning: None
         };
 
-        out.populate_from_data(data_handler, ctx);
+        out.populate_from_data(data_handler);
 
         out
     }
 
-    fn populate_from_data(&mut self, data_handler: &DataHandler, ctx: &mut Context) {
+    fn populate_from_data(&mut self, data_handler: &DataHandler) {
+        self.tiles = self.board_tiles();
+
+        self.update_board(data_handler);
+    }
+
+    fn board_tiles(&self) -> Vec<Tile> {
         let mut tiles = Vec::new();
         for x in 0..8 {
             for y in 0..8 {
                 tiles.push(Tile {
                     position: GridPosition { x, y },
-                    color: if (x + y) % 2 == 0 { [1.0, 0.81, 0.62, 1.0].into() } else { [0.82, 0.55, 0.28, 1.0].into() },
+                    color: if (x + y) % 2 == 0 { self.board_theme.light_color() } else { self.board_theme.dark_color() },
                 });
             }
         }
+        tiles
+    }
+
+    fn set_board_theme(&mut self, board_theme: &BoardTheme) {
+        self.board_theme = board_theme.clone();
+        self.tiles = self.board_tiles();
+    }
 
-        self.tiles = tiles;
+    fn set_piece_set(&mut self, ctx: &mut Context, piece_set: &PieceSet) {
+        self.sprites = PieceSprites::load(ctx, &piece_set.directory);
+        self.refresh_sprites();
+    }
 
-        self.update_board(data_handler, ctx);
+    //Applies what was changed in the theme menu
+    fn theme_changed(&mut self, ctx: &mut Context, theme_menu: &ThemeMenu, change: MenuChange) {
+        match change {
+            MenuChange::BoardTheme => self.set_board_theme(theme_menu.get_board_theme()),
+            MenuChange::PieceSet => self.set_piece_set(ctx, theme_menu.get_piece_set()),
+            MenuChange::None => {}
+        }
     }
 
-    fn update_board(&mut self, data_handler: &DataHandler, ctx: &mut Context) {
+    fn update_board(&mut self, data_handler: &DataHandler) {
         let mut graphics_pieces = Vec::new();
         let pieces = data_handler.board.get_pieces();
 
         for piece in pieces {
-            graphics_pieces.push(GraphicsPiece {
-                sprite: graphics::Image::new(ctx, self.sprites.iter()
-                    .find(|element| (element.0).0 == piece.get_color() && (element.0).1 == piece.get_type()).unwrap().1.clone()).unwrap(),
-                position: GridPosition::from(piece.get_position()),
-            });
+            graphics_pieces.push(self.graphics_piece(piece.get_color(), piece.get_type(), piece.get_position()));
         }
 
-        self.graphics_pieces = graphics_pieces;
+        let old_pieces = std::mem::replace(&mut self.graphics_pieces, graphics_pieces);
+        self.animate_changes(old_pieces);
 
         self.marks.clear();
+        self.promotion_choices.clear();
+    }
+
+    fn graphics_piece(&self, color: schackmotor::Color, piece_type: PieceType, position: Position) -> GraphicsPiece {
+        GraphicsPiece {
+            sprite: self.sprites.get(color, piece_type),
+            color,
+            piece_type,
+            position,
+        }
+    }
+
+    //The four pieces a pawn promoting on `square` can become, in a column from that square towards the middle of the board
+    fn show_promotion_choices(&mut self, square: Position, color: schackmotor::Color) {
+        let ranks = if square.get_y() == 8 { [8, 7, 6, 5] } else { [1, 2, 3, 4] };
+        let piece_types = [PieceType::Queen, PieceType::Knight, PieceType::Rook, PieceType::Bishop];
+
+        self.promotion_choices = piece_types.iter().zip(ranks.iter())
+            .map(|(piece_type, rank)| self.graphics_piece(color, *piece_type, Position::new(square.get_x(), *rank)))
+            .collect();
     }
 
-    fn load_sprites() -> Vec<((schackmotor::Color, schackmotor::PieceType), String)> {
-        let mut sprites = Vec::new();
-        sprites.push(((schackmotor::Color::Black, PieceType::King), "/black_king.png".to_string()));
-        sprites.push(((schackmotor::Color::Black, PieceType::Queen), "/black_queen.png".to_string()));
-        sprites.push(((schackmotor::Color::Black, PieceType::Rook), "/black_rook.png".to_string()));
-        sprites.push(((schackmotor::Color::Black, PieceType::Pawn), "/black_pawn.png".to_string()));
-        sprites.push(((schackmotor::Color::Black, PieceType::Bishop), "/black_bishop.png".to_string()));
-        sprites.push(((schackmotor::Color::Black, PieceType::Knight), "/black_knight.png".to_string()));
-        sprites.push(((schackmotor::Color::White, PieceType::King), "/white_king.png".to_string()));
-        sprites.push(((schackmotor::Color::White, PieceType::Queen), "/white_queen.png".to_string()));
-        sprites.push(((schackmotor::Color::White, PieceType::Rook), "/white_rook.png".to_string()));
-        sprites.push(((schackmotor::Color::White, PieceType::Pawn), "/white_pawn.png".to_string()));
-        sprites.push(((schackmotor::Color::White, PieceType::Bishop), "/white_bishop.png".to_string()));
-        sprites.push(((schackmotor::Color::White, PieceType::Knight), "/white_knight.png".to_string()));
-        sprites
+    fn hide_promotion_choices(&mut self) {
+        self.promotion_choices.clear();
+    }
+
+    fn promotion_choice_at(&self, x: f32, y: f32) -> Option<PieceType> {
+        let position = self.layout.grid_at(x, y)?.to_position(self.flipped);
+        self.promotion_choices.iter().find(|choice| choice.position == position).map(|choice| choice.piece_type)
+    }
+
+    //Slides the pieces that moved between the old board and the new one and fades out those that were taken.
+    //A promoted pawn slides as the piece it became. Boards more than a move apart, like after starting
+    //a new game, aren't animated, and an unchanged board leaves a running animation alone.
+    fn animate_changes(&mut self, old_pieces: Vec<GraphicsPiece>) {
+        let appeared: Vec<(schackmotor::Color, PieceType, Position)> = self.graphics_pieces.iter()
+            .filter(|new| !old_pieces.iter().any(|old| old.is_same(new)))
+            .map(|new| (new.color, new.piece_type, new.position))
+            .collect();
+        let mut vanished: Vec<GraphicsPiece> = old_pieces.into_iter()
+            .filter(|old| !self.graphics_pieces.iter().any(|new| new.is_same(old)))
+            .collect();
+
+        if appeared.is_empty() && vanished.is_empty() {
+            return;
+        }
+        if appeared.len() > 2 || vanished.len() > 3 || self.animation_duration == std::time::Duration::from_millis(0) {
+            self.animation = None;
+            return;
+        }
+
+        let mut moving = Vec::new();
+        for (color, piece_type, position) in appeared {
+            let from = vanished.iter().position(|old| old.color == color && old.piece_type == piece_type)
+                .or_else(|| vanished.iter().position(|old| old.color == color && old.piece_type == PieceType::Pawn));
+            if let Some(index) = from {
+                moving.push((vanished.remove(index).position, position));
+            }
+        }
+
+        self.animation = Some(MoveAnimation {
+            moving,
+            captured: vanished,
+            elapsed: std::time::Duration::from_millis(0),
+        });
+    }
+
+    //How far along the running animation is, eased so pieces speed up and slow down
+    fn animation_progress(&self) -> f32 {
+        let progress = match &self.animation {
+            Some(animation) => (animation.elapsed.as_secs_f32() / self.animation_duration.as_secs_f32()).min(1.0),
+            None => 1.0
+        };
+        progress * progress * (3.0 - 2.0 * progress)
+    }
+
+    fn skip_animation(&mut self) {
+        self.animation = None;
+    }
+
+    //Reads the piece images from disk again and puts them on the pieces already on the board
+    fn reload_sprites(&mut self, ctx: &mut Context) {
+        self.sprites.reload(ctx);
+        self.refresh_sprites();
+    }
+
+    fn refresh_sprites(&mut self) {
+        let sprites = &self.sprites;
+        let animated = self.animation.iter_mut().flat_map(|animation| animation.captured.iter_mut());
+        for graphics_piece in self.graphics_pieces.iter_mut().chain(self.promotion_choices.iter_mut()).chain(animated) {
+            graphics_piece.sprite = sprites.get(graphics_piece.color, graphics_piece.piece_type);
+        }
     }
 
-    fn draw(&mut self, gamestate: &schackmotor::GameState, ctx: &mut Context) -> GameResult {
-        graphics::clear(ctx, [0.5, 0.5, 0.5, 1.0].into());
+    fn draw(&mut self, data_handler: &DataHandler, status_text: Option<String>, analysis: Option<&Analysis>, ctx: &mut Context) -> GameResult {
+        graphics::clear(ctx, self.board_theme.background_color());
 
         for tile in &self.tiles {
-            tile.draw(ctx)?;
+            tile.draw(ctx, &self.layout)?;
         }
 
+        let current_move = self.shown_moves.unwrap_or(data_handler.history.len()).checked_sub(1);
+        self.draw_highlights(ctx, data_handler, current_move)?;
+
+        if self.show_coordinates {
+            self.draw_coordinates(ctx)?;
+        }
+
+        let lifted = self.lifted_piece();
+        let moving: Vec<(Position, Position)> = self.animation.as_ref().map_or(Vec::new(), |animation| animation.moving.clone());
         for graphics_piece in &self.graphics_pieces {
-            graphics_piece.draw(ctx)?;
+            let is_lifted = lifted.is_some_and(|(position, _)| position == graphics_piece.position);
+            let is_moving = moving.iter().any(|(_, to)| *to == graphics_piece.position);
+            if !is_lifted && !is_moving {
+                graphics_piece.draw(ctx, &self.layout, self.flipped)?;
+            }
+        }
+
+        if let Some(animation) = &self.animation {
+            let progress = self.animation_progress();
+            for captured in &animation.captured {
+                captured.draw_at(ctx, &self.layout, self.layout.corner(GridPosition::from_position(captured.position, self.flipped)), 1.0 - progress)?;
+            }
+
+            for (from, to) in &animation.moving {
+                if let Some(graphics_piece) = self.graphics_pieces.iter().find(|graphics_piece| graphics_piece.position == *to) {
+                    let from = self.layout.corner(GridPosition::from_position(*from, self.flipped));
+                    let to = self.layout.corner(GridPosition::from_position(*to, self.flipped));
+                    let dest = ggez::mint::Point2 { x: from.x + (to.x - from.x) * progress, y: from.y + (to.y - from.y) * progress };
+                    graphics_piece.draw_at(ctx, &self.layout, dest, 1.0)?;
+                }
+            }
         }
 
         for mark in &self.marks {
-            mark.draw(ctx)?;
+            mark.draw(ctx, &self.layout, self.flipped)?;
+        }
+
+        //A lifted piece is drawn above everything else on the board
+        if let Some((position, dest)) = lifted {
+            if let Some(graphics_piece) = self.graphics_pieces.iter().find(|graphics_piece| graphics_piece.position == position) {
+                graphics_piece.draw_at(ctx, &self.layout, dest, 1.0)?;
+            }
+        }
+
+        if !self.promotion_choices.is_empty() {
+            self.draw_promotion_choices(ctx)?;
+        }
+
+        if let Some(analysis) = analysis {
+            self.draw_analysis(ctx, analysis, &data_handler.board)?;
         }
 
+        self.draw_history(ctx, data_handler, current_move)?;
+
         let mut middle_of_screen_text = true;
         let mut text: String = "".to_string();
-        match gamestate {
+        match &data_handler.gameover {
+            _ if data_handler.resigned.is_some() => {
+                text = format!("{} resigned", data_handler.resigned.unwrap());
+            }
+            _ if data_handler.timed_out.is_some() => {
+                text = format!("{} ran out of time", data_handler.timed_out.unwrap());
+            }
             schackmotor::GameState::Normal | schackmotor::GameState::Check(_) => {
                 middle_of_screen_text = false
             }
@@ -407,25 +1085,349 @@ impl GraphicsHandler {
         }
 
         if middle_of_screen_text {
-            let gg_text = graphics::Text::new(graphics::TextFragment::from(text)
-                .scale(graphics::Scale { x: 45.0, y: 45.0 }));
+            self.draw_center_text(ctx, text, 45.0, 0.0)?;
+        }
+
+        if let Some(status_text) = status_text {
+            self.draw_center_text(ctx, status_text, 18.0, if middle_of_screen_text { 45.0 } else { 0.0 })?;
+        }
+
+        if let Some(clock) = &data_handler.clock {
+            self.draw_clocks(ctx, clock, data_handler.is_game_over())?;
+        }
+
+        Ok(())
+    }
+
+    //Dims the board and shows the promotion choices on white squares
+    fn draw_promotion_choices(&self, ctx: &mut Context) -> GameResult {
+        let board = graphics::Rect::new(self.layout.left, self.layout.top, self.layout.board_size(), self.layout.board_size());
+        let shade = graphics::Mesh::new_rectangle(ctx, DrawMode::fill(), board, [0.0, 0.0, 0.0, 0.4].into())?;
+        graphics::draw(ctx, &shade, DrawParam::default())?;
+
+        for choice in &self.promotion_choices {
+            let square = self.layout.square(GridPosition::from_position(choice.position, self.flipped));
+            let background = graphics::Mesh::new_rectangle(ctx, DrawMode::fill(), square, [1.0, 1.0, 1.0, 0.9].into())?;
+            graphics::draw(ctx, &background, DrawParam::default())?;
+            choice.draw(ctx, &self.layout, self.flipped)?;
+        }
+
+        Ok(())
+    }
+
+    //The square of the piece being dragged or sliding back, and where the top left corner of its sprite is
+    fn lifted_piece(&mut self) -> Option<(Position, ggez::mint::Point2<f32>)> {
+        if let Some((position, cursor)) = self.dragged {
+            let half = self.layout.cell_size / 2.0;
+            return Some((position, ggez::mint::Point2 { x: cursor.x - half, y: cursor.y - half }));
+        }
+
+        let (position, from, since) = self.returning?;
+        let progress = since.elapsed().as_secs_f32() / RETURN_DURATION.as_secs_f32();
+        if progress >= 1.0 {
+            self.returning = None;
+            return None;
+        }
+
+        let to = self.layout.corner(GridPosition::from_position(position, self.flipped));
+        Some((position, ggez::mint::Point2 { x: from.x + (to.x - from.x) * progress, y: from.y + (to.y - from.y) * progress }))
+    }
+
+    fn start_drag(&mut self, position: Position, x: f32, y: f32) {
+        self.dragged = Some((position, ggez::mint::Point2 { x, y }));
+        self.returning = None;
+        self.animation = None;
+    }
+
+    fn drag_to(&mut self, x: f32, y: f32) {
+        if let Some((_, cursor)) = &mut self.dragged {
+            *cursor = ggez::mint::Point2 { x, y };
+        }
+    }
+
+    fn end_drag(&mut self) {
+        self.dragged = None;
+    }
+
+    fn return_dragged_piece(&mut self) {
+        if let Some((position, cursor)) = self.dragged.take() {
+            let half = self.layout.cell_size / 2.0;
+            self.returning = Some((position, ggez::mint::Point2 { x: cursor.x - half, y: cursor.y - half }, std::time::Instant::now()));
+        }
+    }
+
+    //Tints the squares the last shown move went from and to, and the king's square red while it is in check
+    fn draw_highlights(&self, ctx: &mut Context, data_handler: &DataHandler, current_move: Option<usize>) -> GameResult {
+        let mut highlights = Vec::new();
+
+        if let Some(played) = current_move.and_then(|index| data_handler.history.get(index)) {
+            for square in &[&played.notated.start_position, &played.notated.end_position] {
+                if let Some(position) = notation::position_from_square(square) {
+                    highlights.push((position, [0.95, 0.9, 0.2, 0.45]));
+                }
+            }
+        }
+
+        //The side in check is always the one to move
+        if let schackmotor::GameState::Check(_) | schackmotor::GameState::Checkmate(_) = data_handler.gameover {
+            let in_check = data_handler.board.get_current_player();
+            if let Some(king) = data_handler.board.get_pieces().iter()
+                .find(|piece| piece.get_type() == PieceType::King && piece.get_color() == in_check) {
+                highlights.push((king.get_position(), [0.9, 0.1, 0.1, 0.6]));
+            }
+        }
+
+        for (position, color) in highlights {
+            let square = self.layout.square(GridPosition::from_position(position, self.flipped));
+            let tint = graphics::Mesh::new_rectangle(ctx, DrawMode::fill(), square, color.into())?;
+            graphics::draw(ctx, &tint, DrawParam::default())?;
+        }
+
+        Ok(())
+    }
+
+    //The files along the bottom edge of the board and the ranks along the left edge, in the corners of the squares
+    //and in the color of the other squares so they can be read on both
+    fn draw_coordinates(&self, ctx: &mut Context) -> GameResult {
+        let scale = self.layout.scale();
+        let last = GRID_SIZE.1 as i32 - 1;
+
+        for i in 0..GRID_SIZE.0 as i32 {
+            let labels = [(GridPosition { x: i, y: last }, true), (GridPosition { x: 0, y: i }, false)];
+            for (grid, is_file) in &labels {
+                let position = grid.to_position(self.flipped);
+                let label = if *is_file { ((b'a' + position.get_x() - 1) as char).to_string() } else { position.get_y().to_string() };
+                let color = if (grid.x + grid.y) % 2 == 0 { self.board_theme.dark_color() } else { self.board_theme.light_color() };
+
+                let gg_text = graphics::Text::new(graphics::TextFragment::from(label)
+                    .scale(graphics::Scale { x: 11.0 * scale, y: 11.0 * scale }));
+                let gg_dimensions = gg_text.dimensions(ctx);
+                let square = self.layout.square(*grid);
+                let dest = if *is_file {
+                    ggez::mint::Point2 { x: square.x + square.w - gg_dimensions.0 as f32 - 2.0 * scale, y: square.y + square.h - gg_dimensions.1 as f32 - scale }
+                } else {
+                    ggez::mint::Point2 { x: square.x + 2.0 * scale, y: square.y + scale }
+                };
+                graphics::draw(ctx, &gg_text, DrawParam::default().color(color).dest(dest))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    //The rows of the side panel left for the move history, below and above the clocks if there are any
+    fn history_area(&self, has_clock: bool) -> (f32, usize) {
+        let margin = (if has_clock { 48.0 } else { 8.0 }) * self.layout.scale();
+        let (top, bottom) = (self.layout.top + margin, self.layout.top + self.layout.board_size() - margin);
+        (top, ((bottom - top) / self.row_height()).floor() as usize)
+    }
+
+    fn row_height(&self) -> f32 {
+        HISTORY_ROW_HEIGHT * self.layout.scale()
+    }
+
+    fn column_width(&self) -> f32 {
+        HISTORY_COLUMN_WIDTH * self.layout.scale()
+    }
+
+    fn draw_history(&self, ctx: &mut Context, data_handler: &DataHandler, current: Option<usize>) -> GameResult {
+        let (top, visible_rows) = self.history_area(data_handler.clock.is_some());
+        let offset = data_handler.history_offset();
+        let rows = (data_handler.history.len() + offset + 1) / 2;
+        let last_row = rows.saturating_sub(self.history_scroll);
+        let first_row = last_row.saturating_sub(visible_rows);
+        let first_number = data_handler.start_position.get_fullmove_number() as usize;
+        let scale = self.layout.scale();
+
+        for row in first_row..last_row {
+            let y = top + (row - first_row) as f32 * self.row_height();
+            self.draw_panel_text(ctx, format!("{}.", first_number + row), self.layout.panel_left() + 4.0 * scale, y)?;
+
+            for column in 0..2 {
+                let index = (row * 2 + column).checked_sub(offset);
+                if let Some(played) = index.and_then(|index| data_handler.history.get(index)) {
+                    let x = self.layout.panel_left() + 30.0 * scale + column as f32 * self.column_width();
+                    if current == index {
+                        let highlight = graphics::Mesh::new_rectangle(ctx, DrawMode::fill(),
+                                                                      graphics::Rect::new(x - 2.0 * scale, y, self.column_width(), self.row_height()),
+                                                                      self.board_theme.light_color())?;
+                        graphics::draw(ctx, &highlight, DrawParam::default())?;
+                    }
+                    self.draw_panel_text(ctx, played.san.clone(), x, y)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    //The evaluation, search depth and principal variation along the bottom of the board
+    fn draw_analysis(&self, ctx: &mut Context, analysis: &Analysis, board: &Board) -> GameResult {
+        let top = self.layout.top + self.layout.board_size() - self.row_height();
+        let background = graphics::Mesh::new_rectangle(ctx, DrawMode::fill(),
+                                                       graphics::Rect::new(self.layout.left, top, self.layout.board_size(), self.row_height()),
+                                                       [1.0, 1.0, 1.0, 0.8].into())?;
+        graphics::draw(ctx, &background, DrawParam::default())?;
+
+        let text = format!("{} d{}  {}", analysis.score_text(), analysis.depth, uci::pv_to_san(board, &analysis.pv).join(" "));
+        self.draw_panel_text(ctx, text, self.layout.left + 4.0 * self.layout.scale(), top)
+    }
+
+    fn draw_panel_text(&self, ctx: &mut Context, text: String, x: f32, y: f32) -> GameResult {
+        let scale = self.layout.scale();
+        let gg_text = graphics::Text::new(graphics::TextFragment::from(text)
+            .scale(graphics::Scale { x: 14.0 * scale, y: 14.0 * scale }));
+        graphics::draw(ctx, &gg_text, DrawParam::default().color([0.0, 0.0, 0.0, 1.0].into())
+            .dest(ggez::mint::Point2 { x, y: y + 2.0 * scale }))
+    }
+
+    //The index of the move in the history drawn at the given point of the side panel
+    fn history_index_at(&self, x: f32, y: f32, data_handler: &DataHandler) -> Option<usize> {
+        let (top, visible_rows) = self.history_area(data_handler.clock.is_some());
+        let offset = data_handler.history_offset();
+        let rows = (data_handler.history.len() + offset + 1) / 2;
+        let last_row = rows.saturating_sub(self.history_scroll);
+        let first_row = last_row.saturating_sub(visible_rows);
+
+        let left = self.layout.panel_left() + 28.0 * self.layout.scale();
+        if x < left || x >= left + 2.0 * self.column_width() || y < top {
+            return None;
+        }
+
+        let row = first_row + ((y - top) / self.row_height()).floor() as usize;
+        let column = ((x - left) / self.column_width()).floor() as usize;
+        let index = (row * 2 + column).checked_sub(offset)?;
+
+        if row < last_row && index < data_handler.history.len() { Some(index) } else { None }
+    }
+
+    //Scrolls the history just enough for the given move to be visible
+    fn scroll_to_move(&mut self, index: usize, data_handler: &DataHandler) {
+        let (_, visible_rows) = self.history_area(data_handler.clock.is_some());
+        let offset = data_handler.history_offset();
+        let rows = (data_handler.history.len() + offset + 1) / 2;
+        let row = (index + offset) / 2;
+        let last_row = rows.saturating_sub(self.history_scroll);
+        let first_row = last_row.saturating_sub(visible_rows);
+
+        if row < first_row {
+            self.history_scroll = rows - (row + visible_rows).min(rows);
+        } else if row >= last_row {
+            self.history_scroll = rows - (row + 1);
+        }
+    }
+
+    fn scroll_history(&mut self, rows: f32, data_handler: &DataHandler) {
+        let (_, visible_rows) = self.history_area(data_handler.clock.is_some());
+        let max_scroll = ((data_handler.history.len() + data_handler.history_offset() + 1) / 2).saturating_sub(visible_rows);
+
+        if rows > 0.0 {
+            self.history_scroll = (self.history_scroll + 1).min(max_scroll);
+        } else if rows < 0.0 {
+            self.history_scroll = self.history_scroll.saturating_sub(1);
+        }
+    }
+
+    //The clock of the player at the top of the board at the top of the side panel, the other one at the bottom
+    fn draw_clocks(&self, ctx: &mut Context, clock: &Clock, game_over: bool) -> GameResult {
+        let scale = self.layout.scale();
+        let (panel_width, clock_height) = (SIDE_PANEL_WIDTH * scale, 32.0 * scale);
+        let bottom = self.layout.top + self.layout.board_size();
+        let (top_color, bottom_color) = if self.flipped {
+            (schackmotor::Color::White, schackmotor::Color::Black)
+        } else {
+            (schackmotor::Color::Black, schackmotor::Color::White)
+        };
+        for (color, y) in &[(top_color, self.layout.top + 8.0 * scale), (bottom_color, bottom - 40.0 * scale)] {
+            let running = !game_over && clock.get_to_move() == *color;
+            let background = graphics::Mesh::new_rectangle(ctx, DrawMode::fill(),
+                                                           graphics::Rect::new(self.layout.panel_left() + 8.0 * scale, *y, panel_width - 16.0 * scale, clock_height),
+                                                           if running { [1.0, 1.0, 1.0, 1.0].into() } else { [0.7, 0.7, 0.7, 1.0].into() })?;
+            graphics::draw(ctx, &background, DrawParam::default())?;
+
+            let gg_text = graphics::Text::new(graphics::TextFragment::from(format_duration(clock.remaining(*color)))
+                .scale(graphics::Scale { x: 24.0 * scale, y: 24.0 * scale }));
             let gg_dimensions = gg_text.dimensions(ctx);
-            let background_box = graphics::Mesh::new_rectangle(ctx, DrawMode::fill(),
-                                                               graphics::Rect::new((SCREEN_SIZE.0 - gg_dimensions.0 as f32) / 2f32 as f32 - 8.0,
-                                                                                   (SCREEN_SIZE.0 - gg_dimensions.1 as f32) / 2f32 as f32,
-                                                                                   gg_dimensions.0 as f32 + 16.0, gg_dimensions.1 as f32),
-                                                               [1.0, 1.0, 1.0, 1.0].into())?;
-            graphics::draw(ctx, &background_box, DrawParam::default())?;
             graphics::draw(ctx, &gg_text, DrawParam::default().color([0.0, 0.0, 0.0, 1.0].into())
                 .dest(ggez::mint::Point2 {
-                    x: (SCREEN_SIZE.0 - gg_dimensions.0 as f32) / 2f32 as f32,
-                    y: (SCREEN_SIZE.0 - gg_dimensions.1 as f32) / 2f32 as f32,
+                    x: self.layout.panel_left() + (panel_width - gg_dimensions.0 as f32) / 2.0,
+                    y: *y + (clock_height - gg_dimensions.1 as f32) / 2.0,
                 }))?;
         }
 
         Ok(())
     }
 
+    //A box in the middle of the board with a row per line, the selected one highlighted
+    fn draw_menu(&self, ctx: &mut Context, lines: &[String], selected: usize) -> GameResult {
+        let scale = self.layout.scale();
+        let (padding, line_height) = (8.0 * scale, 24.0 * scale);
+        let texts: Vec<graphics::Text> = lines.iter()
+            .map(|line| graphics::Text::new(graphics::TextFragment::from(line.clone())
+                .scale(graphics::Scale { x: 18.0 * scale, y: 18.0 * scale })))
+            .collect();
+
+        let width = texts.iter().map(|text| text.dimensions(ctx).0 as f32).fold(0.0, f32::max) + 2.0 * padding;
+        let height = texts.len() as f32 * line_height + 2.0 * padding;
+        let left = self.layout.left + (self.layout.board_size() - width) / 2.0;
+        let top = self.layout.top + (self.layout.board_size() - height) / 2.0;
+
+        let background = graphics::Mesh::new_rectangle(ctx, DrawMode::fill(), graphics::Rect::new(left, top, width, height),
+                                                       [1.0, 1.0, 1.0, 1.0].into())?;
+        graphics::draw(ctx, &background, DrawParam::default())?;
+
+        for (i, text) in texts.iter().enumerate() {
+            let y = top + padding + i as f32 * line_height;
+            if i == selected {
+                let highlight = graphics::Mesh::new_rectangle(ctx, DrawMode::fill(), graphics::Rect::new(left, y, width, line_height),
+                                                              self.board_theme.light_color())?;
+                graphics::draw(ctx, &highlight, DrawParam::default())?;
+            }
+            let text_height = text.dimensions(ctx).1 as f32;
+            graphics::draw(ctx, text, DrawParam::default().color([0.0, 0.0, 0.0, 1.0].into())
+                .dest(ggez::mint::Point2 { x: left + padding, y: y + (line_height - text_height) / 2.0 }))?;
+        }
+
+        Ok(())
+    }
+
+    fn draw_center_text(&self, ctx: &mut Context, text: String, scale: f32, y_offset: f32) -> GameResult {
+        let (scale, y_offset) = (scale * self.layout.scale(), y_offset * self.layout.scale());
+        let padding = 8.0 * self.layout.scale();
+        let board_size = self.layout.board_size();
+        let mut gg_text = graphics::Text::new(graphics::TextFragment::from(text.clone())
+            .scale(graphics::Scale { x: scale, y: scale }));
+        let mut gg_dimensions = gg_text.dimensions(ctx);
+
+        //Shrink text that would not fit on the board
+        if gg_dimensions.0 as f32 + 2.0 * padding > board_size {
+            let scale = scale * (board_size - 2.0 * padding) / gg_dimensions.0 as f32;
+            gg_text = graphics::Text::new(graphics::TextFragment::from(text)
+                .scale(graphics::Scale { x: scale, y: scale }));
+            gg_dimensions = gg_text.dimensions(ctx);
+        }
+
+        let background_box = graphics::Mesh::new_rectangle(ctx, DrawMode::fill(),
+                                                           graphics::Rect::new(self.layout.left + (board_size - gg_dimensions.0 as f32) / 2.0 - padding,
+                                                                               self.layout.top + (board_size - gg_dimensions.1 as f32) / 2.0 + y_offset,
+                                                                               gg_dimensions.0 as f32 + 2.0 * padding, gg_dimensions.1 as f32),
+                                                           [1.0, 1.0, 1.0, 1.0].into())?;
+        graphics::draw(ctx, &background_box, DrawParam::default())?;
+        graphics::draw(ctx, &gg_text, DrawParam::default().color([0.0, 0.0, 0.0, 1.0].into())
+            .dest(ggez::mint::Point2 {
+                x: self.layout.left + (board_size - gg_dimensions.0 as f32) / 2.0,
+                y: self.layout.top + (board_size - gg_dimensions.1 as f32) / 2.0 + y_offset,
+            }))
+    }
+
+    //Fits the board to the new window size, the screen coordinates have to follow the window or everything is stretched
+    fn resize(&mut self, ctx: &mut Context, width: f32, height: f32) {
+        if let Err(e) = graphics::set_screen_coordinates(ctx, graphics::Rect::new(0.0, 0.0, width, height)) {
+            println!("Could not resize the board: {}", e);
+        }
+        self.layout = Layout::new(width, height);
+    }
+
     fn add_marked_tile(&mut self, position: schackmotor::Position) {
         self.marks.push(MarkedTile::new(position));
     }
@@ -435,9 +1437,17 @@ impl GraphicsHandler {
     }
 
     fn update(&mut self, data_handler: &mut DataHandler, ctx: &mut Context) {
+        if let Some(animation) = &mut self.animation {
+            animation.elapsed += ggez::timer::delta(ctx);
+            if animation.elapsed >= self.animation_duration {
+                self.animation = None;
+            }
+        }
+
         if data_handler.move_made {
             data_handler.move_made = false;
-            self.update_board(data_handler, ctx);
+            self.history_scroll = 0;
+            self.update_board(data_handler);
         }
     }
 }
@@ -446,66 +1456,354 @@ struct GameState {
     data_handler: Arc<Mutex<DataHandler>>,
     graphics_handler: GraphicsHandler,
     input_handler: InputHandler,
-    network_handler: NetworkHandler,
-    last_network_update: i128
+    network_handler: Option<NetworkHandler>, //None when both players share this window or play the engine
+    engine: Option<Engine>,
+    analyzer: Option<UciEngine>,
+    analyzed_position: Option<String>, //FEN of the position the analyzer is looking at
+    pgn_directory: path::PathBuf,
+    last_network_update: i128,
+    flip_each_move: bool,
+    flip_manually: bool, //turns the board the other way around from where it would normally face
+    local_result_recorded: bool,
+    theme_menu: ThemeMenu,
+    settings: Settings
 }
 
 impl GameState {
-    fn new(ctx: &mut Context, address: String) -> GameResult<GameState> {
-        let board = Board::new(Board::get_standard_layout());
+    fn new(ctx: &mut Context, config: &Config) -> GameResult<GameState> {
+        let start_position = config.start_position.clone().unwrap_or_else(Fen::standard);
+
+        let data_handler = Arc::new(Mutex::new(DataHandler::new(start_position.clone())));
+        let mut input_handler = InputHandler::new();
+        input_handler.always_queen = config.always_queen;
+        input_handler.confirm_moves = config.confirm_moves;
+        let mut engine = None;
+
+        let network_handler = if config.local || config.engine_level.is_some() || config.uci_engine.is_some() {
+            //Nobody has to agree on the game, so it can start right away
+            data_handler.lock().unwrap().set_start_position(start_position, config.time_control);
+            None
+        } else {
+            Some(NetworkHandler::new(config, data_handler.clone()).map_err(ggez::GameError::ConfigError)?)
+        };
+
+        if config.engine_level.is_some() || config.uci_engine.is_some() {
+            let local_color = match config.color {
+                ColorChoice::White => schackmotor::Color::White,
+                ColorChoice::Black => schackmotor::Color::Black,
+                ColorChoice::Random => if rand::random() { schackmotor::Color::White } else { schackmotor::Color::Black }
+            };
+            input_handler.local_color = Some(local_color);
+
+            let opponent = match (&config.uci_engine, config.engine_level) {
+                (Some(path), _) => Engine::with_uci(local_color.invert(), UciEngine::start(path).map_err(ggez::GameError::ConfigError)?),
+                (None, level) => Engine::new(local_color.invert(), level.unwrap_or(1))
+            };
+            graphics::set_window_title(ctx, &format!("Schack - {}", opponent.get_name()));
+            engine = Some(opponent);
+        }
+
+        let analyzer = match &config.analyzer {
+            Some(path) => Some(UciEngine::start(path).map_err(ggez::GameError::ConfigError)?),
+            None => None
+        };
 
-        let data_handler = Arc::new(Mutex::new(DataHandler::new(board)));
-        let graphics_handler = GraphicsHandler::new(&data_handler.lock().unwrap(), ctx);
-        let network_handler = NetworkHandler::new(address, data_handler.clone());
+        let theme_menu = ThemeMenu::load(ctx, &config.board_theme, &config.piece_set);
+        let graphics_handler = GraphicsHandler::new(&data_handler.lock().unwrap(), config.animation_duration,
+                                                    theme_menu.get_board_theme(), theme_menu.get_piece_set(), ctx);
 
-        let mut state = GameState {
+        Ok(GameState {
             data_handler,
             graphics_handler,
-            input_handler: InputHandler::new(),
+            input_handler,
             network_handler,
-            last_network_update: 1
+            engine,
+            analyzer,
+            analyzed_position: None,
+            pgn_directory: config.pgn_directory.clone(),
+            last_network_update: 1,
+            flip_each_move: config.local && config.flip,
+            flip_manually: config.flip_board,
+            local_result_recorded: false,
+            theme_menu,
+            settings: config.settings.clone()
+        })
+    }
+
+    //The board faces the player at this window, or whoever is to move when both share it and want it flipped
+    fn bottom_color(&self, data_handler: &DataHandler) -> schackmotor::Color {
+        match (&self.network_handler, self.input_handler.local_color) {
+            (Some(network_handler), _) => network_handler.get_local_player_color().unwrap_or(schackmotor::Color::White),
+            (None, Some(color)) => color,
+            (None, None) if self.flip_each_move => data_handler.board.get_current_player(),
+            (None, None) => schackmotor::Color::White
+        }
+    }
+
+    fn pgn_headers(&self) -> PgnHeaders {
+        match (&self.network_handler, &self.engine) {
+            (Some(network_handler), _) => network_handler.pgn_headers(),
+            (None, Some(engine)) if engine.get_color() == schackmotor::Color::White => {
+                PgnHeaders::new("Schack engine game", engine.get_name(), "?".to_string(), None)
+            }
+            (None, Some(engine)) => PgnHeaders::new("Schack engine game", "?".to_string(), engine.get_name(), None),
+            (None, None) => PgnHeaders::new("Schack local game", "?".to_string(), "?".to_string(), None)
+        }
+    }
+
+    fn send_to_peer(&self, request: PeerRequest) {
+        let network_handler = match &self.network_handler {
+            Some(network_handler) => network_handler,
+            None => return
         };
 
-        Ok(state)
+        match request {
+            PeerRequest::Draw(action) => match network_handler.send_draw_action(action) {
+                Ok(true) => self.data_handler.lock().unwrap().agree_draw(),
+                Ok(false) => {}
+                Err(e) => println!("{}", e)
+            }
+            PeerRequest::Resign => {
+                if let Err(e) = network_handler.resign() {
+                    println!("{}", e);
+                }
+            }
+        }
+    }
+
+    fn save_settings(&self) {
+        if let Err(e) = self.settings.save() {
+            println!("{}", e);
+        }
+    }
+
+    fn copy_fen(&self) {
+        let fen = self.data_handler.lock().unwrap().current_fen().to_string();
+        match ClipboardContext::new().and_then(|mut clipboard| clipboard.set_contents(fen.clone())) {
+            Ok(()) => println!("Copied {}", fen),
+            Err(e) => println!("Could not copy to the clipboard: {}", e)
+        }
+    }
+
+    //Starts the game from the FEN on the clipboard, as long as it hasn't started yet. A local game
+    //can be started over from a new position at any time.
+    fn paste_fen(&mut self) {
+        let start_position = ClipboardContext::new().and_then(|mut clipboard| clipboard.get_contents())
+            .map_err(|e| format!("Could not paste from the clipboard: {}", e))
+            .and_then(|text| text.parse::<Fen>());
+
+        let result = start_position.and_then(|start_position| {
+            if let Some(network_handler) = &self.network_handler {
+                network_handler.set_start_position(start_position.clone())?;
+            }
+            let mut data_handler = self.data_handler.lock().unwrap();
+            let time_control = data_handler.clock.as_ref().map(|clock| clock.get_time_control());
+            data_handler.set_start_position(start_position, time_control);
+            Ok(())
+        });
+
+        if let Err(e) = result {
+            println!("{}", e);
+        }
     }
 }
 
 impl event::EventHandler for GameState {
     fn update(&mut self, ctx: &mut Context) -> GameResult {
-        self.graphics_handler.update(&mut self.data_handler.lock().unwrap(), ctx);
+        let playing = self.network_handler.as_ref()
+            .is_none_or(|network_handler| network_handler.get_local_player_color().is_some());
+        if playing {
+            self.data_handler.lock().unwrap().tick(ggez::timer::delta(ctx));
+        }
+
+        {
+            let mut data_handler = self.data_handler.lock().unwrap();
+            self.graphics_handler.flipped = (self.bottom_color(&data_handler) == schackmotor::Color::Black) != self.flip_manually;
+            self.graphics_handler.update(&mut data_handler, ctx);
+        }
 
-        if (self.last_network_update * 1000 - ggez::timer::time_since_start(ctx).as_millis() as i128) < 0
-        && self.network_handler.get_local_player_color().is_none(){
-            self.last_network_update += 1;
-            self.network_handler.set_local_color(schackmotor::Color::White);
-            self.network_handler.send(format!("http://{}/start-game", self.network_handler.get_target_address()).as_str(), "{\"color\":\"white\"}".to_string());
+        if let Some(engine) = &mut self.engine {
+            engine.update(&self.data_handler);
+        }
+
+        if let Some(analyzer) = &mut self.analyzer {
+            //The analyzer is told about the position after letting go of the lock, which the listener may need
+            let (position, start_position, moves) = {
+                let data_handler = self.data_handler.lock().unwrap();
+                (data_handler.current_fen().to_string(), data_handler.start_position.clone(),
+                 data_handler.history.iter().map(|played| played.notated.clone()).collect::<Vec<NotatedMove>>())
+            };
+            if self.analyzed_position.as_ref() != Some(&position) {
+                if let Err(e) = analyzer.analyse(&start_position, &moves) {
+                    println!("{}", e);
+                }
+                self.analyzed_position = Some(position);
+            }
+        }
+
+        if let Some(network_handler) = &mut self.network_handler {
+            if (self.last_network_update * 1000 - ggez::timer::time_since_start(ctx).as_millis() as i128) < 0
+            && network_handler.get_local_player_color().is_none(){
+                self.last_network_update += 1;
+                network_handler.start_game();
+            }
+        }
+
+        let (game_over, winner) = {
+            let data_handler = self.data_handler.lock().unwrap();
+            (data_handler.is_game_over(), data_handler.winner())
+        };
+        let game_finished = match &mut self.network_handler {
+            Some(network_handler) => {
+                let game_finished = network_handler.record_result(game_over, winner);
+                if game_finished {
+                    let (wins, losses) = network_handler.get_score();
+                    graphics::set_window_title(ctx, &format!("Schack - You {} - {} Opponent", wins, losses));
+                }
+                game_finished
+            }
+            None => {
+                let game_finished = game_over && !self.local_result_recorded;
+                self.local_result_recorded = game_over;
+                game_finished
+            }
+        };
+        if game_finished {
+            self.data_handler.lock().unwrap().save_pgn(self.pgn_headers(), &self.pgn_directory);
         }
 
         Ok(())
     }
 
     fn draw(&mut self, ctx: &mut Context) -> GameResult {
-        self.graphics_handler.draw(&self.data_handler.lock().unwrap().gameover, ctx)?;
+        let data_handler = self.data_handler.lock().unwrap();
+        let status_text = self.input_handler.get_prompt_text()
+            .or_else(|| match &self.network_handler {
+                Some(network_handler) => network_handler.get_status_text(data_handler.is_game_over()),
+                None if data_handler.is_game_over() => Some("M for a new game".to_string()),
+                None if self.engine.as_ref().is_some_and(|engine| engine.is_thinking()) => Some("Thinking...".to_string()),
+                None => None
+            });
+        let analysis = self.analyzer.as_ref().map(|analyzer| analyzer.get_analysis());
+        self.graphics_handler.draw(&data_handler, status_text, analysis.as_ref(), ctx)?;
+
+        if self.theme_menu.is_open() {
+            let (lines, selected) = self.theme_menu.lines();
+            self.graphics_handler.draw_menu(ctx, &lines, selected)?;
+        }
 
         graphics::present(ctx)?;
 
         Ok(())
     }
 
-    fn mouse_button_up_event(&mut self, ctx: &mut Context, button: MouseButton, x: f32, y: f32) {
-        self.input_handler.clicked_at(ctx, button, x, y, &mut self.data_handler.lock().unwrap(), &mut self.graphics_handler, &self.network_handler);
+    fn mouse_button_down_event(&mut self, _ctx: &mut Context, button: MouseButton, x: f32, y: f32) {
+        self.graphics_handler.skip_animation();
+        self.input_handler.pressed_at(button, x, y, &self.data_handler.lock().unwrap(), &mut self.graphics_handler, self.network_handler.as_ref());
+    }
 
+    fn mouse_motion_event(&mut self, _ctx: &mut Context, x: f32, y: f32, _dx: f32, _dy: f32) {
+        self.graphics_handler.drag_to(x, y);
     }
 
-    fn key_down_event(&mut self, ctx: &mut Context, keycode: ggez::event::KeyCode, _keymod: ggez::event::KeyMods, _repeat: bool) {
-        self.input_handler.key_pressed(ctx, keycode, &mut self.data_handler.lock().unwrap(), &mut self.graphics_handler, &self.network_handler);
+    fn mouse_button_up_event(&mut self, _ctx: &mut Context, button: MouseButton, x: f32, y: f32) {
+        self.input_handler.clicked_at(button, x, y, &mut self.data_handler.lock().unwrap(), &mut self.graphics_handler, self.network_handler.as_ref());
     }
-}
 
-pub fn main() -> GameResult {
-    let stdin = std::io::stdin();
-    let address = stdin.lock().lines().next().unwrap().unwrap();
+    fn mouse_wheel_event(&mut self, _ctx: &mut Context, _x: f32, y: f32) {
+        self.graphics_handler.scroll_history(y, &self.data_handler.lock().unwrap());
+    }
+
+    fn resize_event(&mut self, ctx: &mut Context, width: f32, height: f32) {
+        self.graphics_handler.resize(ctx, width, height);
+    }
+
+    fn key_down_event(&mut self, ctx: &mut Context, keycode: ggez::event::KeyCode, keymod: ggez::event::KeyMods, _repeat: bool) {
+        //The menu takes every key while it is open
+        if self.theme_menu.is_open() {
+            let change = self.theme_menu.key_pressed(keycode);
+            self.graphics_handler.theme_changed(ctx, &self.theme_menu, change);
+            if change != MenuChange::None {
+                self.settings.save_themes(&self.theme_menu);
+            }
+            return;
+        }
+
+        if keymod.contains(ggez::event::KeyMods::CTRL) {
+            match keycode {
+                KeyCode::C => self.copy_fen(),
+                KeyCode::V => self.paste_fen(),
+                _ => {}
+            }
+            return;
+        }
+
+        if let (KeyCode::Return, Some(network_handler)) = (keycode, &mut self.network_handler) {
+            network_handler.retry_start_game();
+        }
+
+        if keycode == KeyCode::O {
+            self.flip_manually = !self.flip_manually;
+            self.settings.flip_board = self.flip_manually;
+            self.save_settings();
+            return;
+        }
+
+        if keycode == KeyCode::T {
+            self.theme_menu.toggle();
+            return;
+        }
+
+        if keycode == KeyCode::Space {
+            self.graphics_handler.skip_animation();
+            return;
+        }
 
+        if keycode == KeyCode::F5 {
+            self.graphics_handler.reload_sprites(ctx);
+            return;
+        }
+
+        if keycode == KeyCode::C {
+            self.graphics_handler.show_coordinates = !self.graphics_handler.show_coordinates;
+            return;
+        }
+
+        if keycode == KeyCode::S && !self.input_handler.awaiting_promotion() {
+            self.data_handler.lock().unwrap().save_pgn(self.pgn_headers(), &self.pgn_directory);
+            return;
+        }
+
+        if keycode == KeyCode::M {
+            if !self.data_handler.lock().unwrap().is_game_over() {
+                return;
+            }
+
+            //A local game can simply be started over. The lock is not held while the peer is asked,
+            //as its listener needs our DataHandler if it accepts at the same time.
+            let rematch = match &mut self.network_handler {
+                Some(network_handler) => network_handler.request_rematch(),
+                None => Ok(true)
+            };
+            match rematch {
+                Ok(true) => {
+                    self.data_handler.lock().unwrap().reset();
+                    self.input_handler.reset_clicked_squares();
+                }
+                Ok(false) => {}
+                Err(e) => println!("{}", e)
+            }
+            return;
+        }
+
+        let request = self.input_handler.key_pressed(keycode, &mut self.data_handler.lock().unwrap(), &mut self.graphics_handler, self.network_handler.as_ref());
+        if let Some(request) = request {
+            self.send_to_peer(request);
+        }
+    }
+}
+
+fn context_builder() -> ggez::ContextBuilder {
     let resource_dir = if let Ok(manifest_dir) = env::var("CARGO_MANIFEST_DIR") {
         let mut path = path::PathBuf::from(manifest_dir);
         path.push("resources");
@@ -514,11 +1812,37 @@ pub fn main() -> GameResult {
         path::PathBuf::from("./resources")
     };
 
-    let cb = ggez::ContextBuilder::new("schack", "eskil").add_resource_path(resource_dir)
+    ggez::ContextBuilder::new("schack", "eskil").add_resource_path(resource_dir)
         .window_setup(ggez::conf::WindowSetup::default().title("Schack").icon("/icon.ico"))
-        .window_mode(ggez::conf::WindowMode::default().dimensions(SCREEN_SIZE.0, SCREEN_SIZE.1).resizable(false));
-    let (ctx, event_loop) = &mut cb.build()?;
+        .window_mode(ggez::conf::WindowMode::default().dimensions(SCREEN_SIZE.0, SCREEN_SIZE.1)
+            .resizable(true).min_dimensions(SCREEN_SIZE.0 / 2.0, SCREEN_SIZE.1 / 2.0))
+}
+
+pub fn main() -> GameResult {
+    let config = match Config::from_env_and_args() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
+    if let Some(replay_path) = &config.replay {
+        let (ctx, event_loop) = &mut context_builder().build()?;
+        let state = &mut replay::ReplayState::new(ctx, replay_path, &config)?;
+        return event::run(ctx, event_loop, state);
+    }
+
+    if config.headless {
+        if let Err(e) = headless::run(&config) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let (ctx, event_loop) = &mut context_builder().build()?;
 
-    let state = &mut GameState::new(ctx, address)?;
+    let state = &mut GameState::new(ctx, &config)?;
     event::run(ctx, event_loop, state)
 }
\ No newline at end of file
@@ -1,15 +1,25 @@
 use std::thread;
 use crate::{DataHandler, NotatedMove};
+use crate::config::Config;
+use crate::fen::Fen;
+use crate::pgn::PgnHeaders;
+use crate::handshake::{Handshake, HandshakeState};
+use crate::protocol::{DeclineReason, ErrorResponse, Message, MoveResponse, StartGameResponse, DrawAction, DrawRequest, DrawResponse, RematchRequest, RematchResponse, ResignRequest, ResignResponse, ProtocolError};
 use std::sync::{Mutex, Arc};
-use regex::Regex;
 use std::io::Read;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
 
 pub(crate) struct NetworkHandler {
     client: reqwest::Client,
     target_address: String,
+    local_address: String,
     local_color: Arc<Mutex<Option<schackmotor::Color>>>,
+    handshake: Arc<Mutex<Handshake>>,
     data_handler: Arc<Mutex<DataHandler>>,
-    score: (usize, usize), //number of times white has won, number of times black has won
+    score: (usize, usize), //number of times you have won, number of times your opponent has won
+    result_recorded: bool,
+    games_finished: usize,
     draw_requested: Arc<Mutex<(bool, bool)>>, //you, the guy she tells you not to worry about/your opponent
     rematch_requested: Arc<Mutex<(bool, bool)>> //you, the guy she tells you not to worry about/your opponent
 }
@@ -27,146 +37,368 @@ impl NetworkHandler {
         *self.local_color.lock().unwrap() = Some(color);
     }
 
-    pub(crate) fn new(target_address: String, data_handler: Arc<Mutex<DataHandler>>) -> Self {
+    //Proposes a game to the opponent unless one is already proposed, agreed on or declined
+    pub(crate) fn start_game(&mut self) {
+        let proposal = self.handshake.lock().unwrap().propose();
+
+        if let Some(request) = proposal {
+            let response: Result<StartGameResponse, String> = self.send("/start-game", &request);
+            if let Err(e) = &response {
+                println!("{}", e);
+            }
+
+            let (color, start_position, time_control) = {
+                let mut handshake = self.handshake.lock().unwrap();
+                (handshake.answered(request.nonce, response), handshake.get_start_position(), handshake.get_time_control())
+            };
+
+            if let Some(color) = color {
+                self.data_handler.lock().unwrap().set_start_position(start_position, time_control);
+                self.set_local_color(color);
+            }
+        }
+    }
+
+    //Changes the position proposed to the opponent, only possible before the game has started
+    pub(crate) fn set_start_position(&self, start_position: Fen) -> Result<(), String> {
+        self.handshake.lock().unwrap().set_start_position(start_position)
+    }
+
+    //Lets a declined handshake be proposed again
+    pub(crate) fn retry_start_game(&mut self) {
+        self.handshake.lock().unwrap().retry();
+    }
+
+    pub(crate) fn get_status_text(&self, game_over: bool) -> Option<String> {
+        match self.handshake.lock().unwrap().get_state() {
+            HandshakeState::Idle | HandshakeState::Proposed(_) => Some("Waiting for opponent".to_string()),
+            HandshakeState::Declined(DeclineReason::ColorTaken) => Some("Opponent wants the same color, Enter to play either".to_string()),
+            HandshakeState::Refused(DeclineReason::ColorTaken) => Some("You want the same color as the opponent, Enter to play either".to_string()),
+            HandshakeState::Declined(reason) => Some(format!("Opponent declined ({})", reason)),
+            HandshakeState::Refused(reason) => Some(format!("Declined the opponent's game ({})", reason)),
+            HandshakeState::Playing if game_over => {
+                let (requested, opponent_requested) = self.get_rematch_requests();
+                if requested {
+                    Some("Rematch requested".to_string())
+                } else if opponent_requested {
+                    Some("Opponent wants a rematch (M)".to_string())
+                } else {
+                    Some(format!("You {} - {} Opponent, M for rematch", self.score.0, self.score.1))
+                }
+            }
+            HandshakeState::Playing => {
+                let (offered, opponent_offered) = self.get_draw_offers();
+                if opponent_offered {
+                    Some("Opponent offers a draw (Y/N)".to_string())
+                } else if offered {
+                    Some("Draw offered (D to withdraw)".to_string())
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    pub(crate) fn get_draw_offers(&self) -> (bool, bool) {
+        *self.draw_requested.lock().unwrap()
+    }
+
+    //A draw offer lapses once a move is played
+    pub(crate) fn clear_draw_offers(&self) {
+        *self.draw_requested.lock().unwrap() = (false, false);
+    }
+
+    //Returns whether both players have now agreed to a draw
+    pub(crate) fn send_draw_action(&self, action: DrawAction) -> Result<bool, String> {
+        {
+            let mut draw_requested = self.draw_requested.lock().unwrap();
+            match action {
+                DrawAction::Offer => draw_requested.0 = true,
+                DrawAction::Accept if !draw_requested.1 => return Err("No draw has been offered".to_string()),
+                DrawAction::Accept | DrawAction::Decline => draw_requested.1 = false,
+                DrawAction::Withdraw => draw_requested.0 = false
+            }
+        }
+
+        let response: DrawResponse = self.send("/request-draw", &DrawRequest { action })?;
+
+        if response.draw_accepted {
+            *self.draw_requested.lock().unwrap() = (false, false);
+        }
+
+        Ok(response.draw_accepted)
+    }
+
+    pub(crate) fn get_score(&self) -> (usize, usize) {
+        self.score
+    }
+
+    //Counts a finished game towards the score once, returns whether a game has just finished
+    pub(crate) fn record_result(&mut self, game_over: bool, winner: Option<schackmotor::Color>) -> bool {
+        let local_color = match self.get_local_player_color() {
+            Some(color) => color,
+            None => return false
+        };
+
+        if !game_over {
+            self.result_recorded = false;
+            return false;
+        }
+
+        if self.result_recorded {
+            return false;
+        }
+
+        self.result_recorded = true;
+        self.games_finished += 1;
+        match winner {
+            Some(winner) if winner == local_color => self.score.0 += 1,
+            Some(_) => self.score.1 += 1,
+            None => {}
+        }
+        true
+    }
+
+    //The number of the game in progress, or of the one that just finished
+    pub(crate) fn get_round(&self) -> usize {
+        if self.result_recorded { self.games_finished } else { self.games_finished + 1 }
+    }
+
+    //The addresses of the white and the black player
+    pub(crate) fn get_player_names(&self) -> (String, String) {
+        match self.get_local_player_color() {
+            Some(schackmotor::Color::Black) => (self.target_address.clone(), self.local_address.clone()),
+            _ => (self.local_address.clone(), self.target_address.clone())
+        }
+    }
+
+    pub(crate) fn pgn_headers(&self) -> PgnHeaders {
+        let (white, black) = self.get_player_names();
+        PgnHeaders::new("Schack network game", white, black, Some(self.get_round()))
+    }
+
+    pub(crate) fn resign(&self) -> Result<(), String> {
+        let response: ResignResponse = self.send("/resign", &ResignRequest {})?;
+        if !response.acknowledged {
+            return Err("The opponent didn't accept the resignation, their game may already be over".to_string());
+        }
+        Ok(())
+    }
+
+    pub(crate) fn get_rematch_requests(&self) -> (bool, bool) {
+        *self.rematch_requested.lock().unwrap()
+    }
+
+    //Returns whether both players now want a rematch, in which case colors have been swapped
+    //and the caller should reset the board
+    pub(crate) fn request_rematch(&mut self) -> Result<bool, String> {
+        self.rematch_requested.lock().unwrap().0 = true;
+
+        let response: RematchResponse = self.send("/request-rematch", &RematchRequest {})?;
+
+        if response.rematch_accepted {
+            start_rematch(&self.local_color, &self.draw_requested, &self.rematch_requested);
+        }
+
+        Ok(response.rematch_accepted)
+    }
+
+    pub(crate) fn new(config: &Config, data_handler: Arc<Mutex<DataHandler>>) -> Result<Self, String> {
+        let server = tiny_http::Server::http(config.listen_address())
+            .map_err(|e| format!("Could not listen on {}: {}", config.listen_address(), e))?;
+
         let mut out = NetworkHandler {
             client: reqwest::Client::new(),
-            target_address,
+            target_address: config.peer_address.clone(),
+            local_address: config.listen_address(),
             local_color: Arc::new(Mutex::new(None)),
+            handshake: Arc::new(Mutex::new(Handshake::new(config.color, config.time_control, config.start_position.clone()))),
             data_handler,
             score: (0, 0),
+            result_recorded: false,
+            games_finished: 0,
             draw_requested: Arc::new(Mutex::new((false, false))),
             rematch_requested: Arc::new(Mutex::new((false, false)))
         };
 
-        out.listen();
+        out.listen(server);
 
-        out
+        Ok(out)
     }
 
-    fn listen(&mut self) {
+    fn listen(&mut self, server: tiny_http::Server) {
         let data_handler2 = self.data_handler.clone();
         let local_color_ref = self.local_color.clone();
+        let handshake_ref = self.handshake.clone();
         let request_draw_ref = self.draw_requested.clone();
         let request_rematch_ref = self.rematch_requested.clone();
-        let address = self.target_address.clone();
 
         thread::spawn( move || {
-            let server = tiny_http::Server::http("0.0.0.0:7878").unwrap();
-            let regex_for_start_square = Regex::new("\"start_square\"(\\s)*:(\\s)*\"[a-h][1-8]\"").unwrap();
-            let regex_for_end_square = Regex::new("\"end_square\"(\\s)*:(\\s)*\"[a-h][1-8]\"").unwrap();
-            let regex_for_promotion = Regex::new("\"promotes_to\"(\\s)*:(\\s)*\"[QRBN]\"").unwrap();
-            let regex_for_square_extraction = Regex::new("[a-h][1-8]").unwrap();
-            let regex_for_promotion_extraction = Regex::new("[QRBN]").unwrap();
-
             loop {
                 let mut request = match server.recv() {
                     Ok(rq) => rq,
-                    Err(e) => { panic!("error: {}", e); }
+                    Err(e) => {
+                        println!("error: {}", e);
+                        break;
+                    }
                 };
 
                 let mut request_text = "".to_string();
 
-                request.as_reader().read_to_string(&mut request_text).unwrap();
+                if let Err(e) = request.as_reader().read_to_string(&mut request_text) {
+                    let error = ProtocolError::MalformedBody(e.to_string());
+                    respond(request, error.status_code(), &error.to_response());
+                    continue;
+                }
 
                 println!("{}", request_text);
 
-                match request.method() {
-                    tiny_http::Method::Get => {}
+                let method = request.method().clone();
+                match method {
                     tiny_http::Method::Post => {
-                        let url = request.url();
-                        let mut response_body = "".to_string();
-                        let mut response_code = 0u32;
-
-                        if url == "/start-game" {
-                            if local_color_ref.lock().unwrap().is_none() {
-                                if request_text.contains("white") {
-                                    *local_color_ref.lock().unwrap() = Some(schackmotor::Color::Black);
-                                    response_body = "{\"accepted\":true}".to_string();
-                                    response_code = 200;
-                                } else if request_text.contains("black") {
-                                    *local_color_ref.lock().unwrap() = Some(schackmotor::Color::White);
-                                    response_body = "{\"accepted\":true}".to_string();
-                                    response_code = 200;
-                                } else {
-                                    response_body = "{\"accepted\":false}".to_string();
-                                    response_code = 400;
+                        let message = match Message::parse(request.url(), &request_text) {
+                            Ok(message) => message,
+                            Err(error) => {
+                                respond(request, error.status_code(), &error.to_response());
+                                continue;
+                            }
+                        };
+
+                        match message {
+                            Message::StartGame(start_game) => {
+                                let (response, color, start_position) = {
+                                    let mut handshake = handshake_ref.lock().unwrap();
+                                    let (response, color) = handshake.receive(&start_game);
+                                    (response, color, handshake.get_start_position())
+                                };
+                                if color.is_some() {
+                                    data_handler2.lock().unwrap().set_start_position(start_position, start_game.time_control);
+                                    *local_color_ref.lock().unwrap() = color;
                                 }
+                                respond(request, if response.accepted { 200 } else { 409 }, &response);
                             }
-                        } else if url == "/move" {
-                            if regex_for_start_square.is_match(request_text.as_ref())
-                                && regex_for_end_square.is_match(request_text.as_ref()) {
-
-                                let start_square = regex_for_square_extraction.captures(
-                                    regex_for_start_square.captures(request_text.as_ref()).unwrap()
-                                        .get(0).unwrap().as_str()).unwrap().get(0).unwrap().as_str();
-                                let end_square = regex_for_square_extraction.captures(
-                                    regex_for_end_square.captures(request_text.as_ref()).unwrap()
-                                        .get(0).unwrap().as_str()).unwrap().get(0).unwrap().as_str();
-                                let res;
-
-                                if regex_for_promotion.is_match(request_text.as_ref()) {
-                                    let promotes_to = regex_for_promotion_extraction.captures(
-                                        regex_for_promotion.captures(request_text.as_ref())
-                                            .unwrap().get(0).unwrap().as_str()).unwrap()
-                                        .get(0).unwrap().as_str();
-
-                                    res = data_handler2.lock().unwrap().receive_move(
-                                        NotatedMove::new(start_square.to_string(), end_square.to_string(),
-                                                         Some(promotes_to.to_string())), local_color_ref.lock().unwrap().unwrap());
-
-                                } else {
-                                    res = data_handler2.lock().unwrap().receive_move(
-                                        NotatedMove::new(start_square.to_string(), end_square.to_string(), None),
-                                        local_color_ref.lock().unwrap().unwrap());
+                            Message::Move(mov) => {
+                                let local_color = *local_color_ref.lock().unwrap();
+                                let clock = mov.clock;
+                                let res = match local_color {
+                                    Some(color) => {
+                                        let mut data_handler = data_handler2.lock().unwrap();
+                                        let res = data_handler.receive_move(NotatedMove::from(mov), color.invert());
+                                        if let (Ok(_), Some(clock)) = (&res, clock) {
+                                            data_handler.sync_clock(&clock);
+                                        }
+                                        res
+                                    }
+                                    None => Err("No game in progress".to_string())
+                                };
+
+                                let valid_move = res.is_ok();
+                                if valid_move {
+                                    *request_draw_ref.lock().unwrap() = (false, false);
                                 }
+                                respond(request, if valid_move { 200 } else { 400 }, &MoveResponse { valid_move });
+                            }
+                            Message::RequestDraw(draw) => {
+                                let draw_accepted = {
+                                    let mut draw_requested = request_draw_ref.lock().unwrap();
+                                    match draw.action {
+                                        DrawAction::Offer => draw_requested.1 = true,
+                                        DrawAction::Accept => {}
+                                        DrawAction::Decline => draw_requested.0 = false,
+                                        DrawAction::Withdraw => draw_requested.1 = false
+                                    }
 
-                                match res {
-                                    Ok(_) => {
-                                        response_body = "{\"valid_move\":true}".to_string();
-                                        response_code = 200;
+                                    let draw_accepted = draw_requested.0
+                                        && (draw.action == DrawAction::Offer || draw.action == DrawAction::Accept);
+                                    if draw_accepted {
+                                        *draw_requested = (false, false);
                                     }
-                                    Err(_) => {
-                                        response_body = "{\"valid_move\":false}".to_string();
-                                        response_code = 400;
+                                    draw_accepted
+                                };
+
+                                if draw_accepted {
+                                    data_handler2.lock().unwrap().agree_draw();
+                                }
+                                respond(request, 200, &DrawResponse { draw_accepted });
+                            }
+                            Message::Resign(_) => {
+                                let local_color = *local_color_ref.lock().unwrap();
+                                let acknowledged = match local_color {
+                                    Some(color) => {
+                                        let mut data_handler = data_handler2.lock().unwrap();
+                                        let in_progress = !data_handler.is_game_over();
+                                        data_handler.resign(color.invert());
+                                        in_progress
                                     }
+                                    None => false
+                                };
+                                respond(request, if acknowledged { 200 } else { 409 }, &ResignResponse { acknowledged });
+                            }
+                            Message::RequestRematch(_) => {
+                                let rematch_accepted = {
+                                    let mut rematch_requested = request_rematch_ref.lock().unwrap();
+                                    rematch_requested.1 = true;
+                                    rematch_requested.0
+                                };
+
+                                if rematch_accepted {
+                                    start_rematch(&local_color_ref, &request_draw_ref, &request_rematch_ref);
+                                    data_handler2.lock().unwrap().reset();
                                 }
+                                respond(request, 200, &RematchResponse { rematch_accepted });
                             }
-                        } else if url == "/request-draw" {
-                            request_draw_ref.lock().unwrap().1 = true;
-                            response_body = format!("{0}\"draw_accepted\":{2}{1}", "{", "}", request_draw_ref.lock().unwrap().0);
-                            response_code = 200;
-                        } else if url == "/request-rematch" {
-                            request_rematch_ref.lock().unwrap().1 = true;
-                            response_body = format!("{0}\"draw_accepted\":{2}{1}", "{", "}", request_rematch_ref.lock().unwrap().0);
-                            response_code = 200;
                         }
-
-                        let mut response = tiny_http::Response::from_string(response_body);
-                        response = response.with_status_code(tiny_http::StatusCode::from(response_code));
-
-                        request.respond(response).unwrap();
                     }
                     _ => {
                         let mut res = tiny_http::Response::from_string("");
                         res = res.with_status_code(tiny_http::StatusCode::from(405));
-                        request.respond(res).unwrap();
+                        request.respond(res).unwrap_or_else(|e| println!("error: {}", e));
                     }
                 }
             }
         });
     }
 
-    pub(crate) fn send(&self, url: &str, message: String) -> String {
-        let mut headers = reqwest::header::HeaderMap::new();
-        headers.insert(reqwest::header::HOST, reqwest::header::HeaderValue::from_bytes(url.as_ref()).unwrap());
-        headers.insert(reqwest::header::CONTENT_TYPE, reqwest::header::HeaderValue::from_bytes(b"text/json").unwrap());
-        let mut var = self.client.post(url).headers(headers).body(message).send().unwrap();
+    pub(crate) fn send<T: Serialize, R: DeserializeOwned>(&self, endpoint: &str, message: &T) -> Result<R, String> {
+        let url = format!("http://{}{}", self.target_address, endpoint);
+        let body = serde_json::to_string(message).map_err(|e| e.to_string())?;
+
+        let mut response = self.client.post(url.as_str())
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body)
+            .send()
+            .map_err(|e| e.to_string())?;
 
         let mut text = "".to_string();
-        var.read_to_string(&mut text);
+        response.read_to_string(&mut text).map_err(|e| e.to_string())?;
+
+        println!("{} {}", response.status(), text);
 
-        println!("{}", text);
+        //Failed requests may carry an explanation instead of the usual reply
+        if !response.status().is_success() {
+            if let Ok(error) = serde_json::from_str::<ErrorResponse>(&text) {
+                return Err(format!("{} from {}: {}", response.status(), endpoint, error.error));
+            }
+        }
 
-        text
+        serde_json::from_str(&text).map_err(|e| format!("Malformed response from {}: {}", endpoint, e))
     }
-}
\ No newline at end of file
+}
+
+fn start_rematch(local_color: &Mutex<Option<schackmotor::Color>>, draw_requested: &Mutex<(bool, bool)>,
+                 rematch_requested: &Mutex<(bool, bool)>) {
+    let mut local_color = local_color.lock().unwrap();
+    *local_color = local_color.map(|color| color.invert());
+    *draw_requested.lock().unwrap() = (false, false);
+    *rematch_requested.lock().unwrap() = (false, false);
+}
+
+fn respond<T: Serialize>(request: tiny_http::Request, status_code: u16, body: &T) {
+    let body = serde_json::to_string(body).unwrap_or_default();
+    let header = tiny_http::Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap();
+    let response = tiny_http::Response::from_string(body)
+        .with_status_code(tiny_http::StatusCode::from(status_code))
+        .with_header(header);
+
+    request.respond(response).unwrap_or_else(|e| println!("error: {}", e));
+}
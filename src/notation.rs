@@ -0,0 +1,245 @@
+use schackmotor::{Board, PieceType, Position};
+use crate::NotatedMove;
+use crate::fen::Fen;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PlayedMove {
+    pub(crate) notated: NotatedMove,
+    pub(crate) san: String
+}
+
+pub(crate) fn position_from_square(square: &str) -> Option<Position> {
+    let bytes = square.as_bytes();
+    if !crate::protocol::is_square(square) {
+        return None;
+    }
+    Some(Position::new(bytes[0] - b'a' + 1, bytes[1] - b'1' + 1))
+}
+
+pub(crate) fn piece_letter(piece_type: PieceType) -> &'static str {
+    match piece_type {
+        PieceType::King => "K",
+        PieceType::Queen => "Q",
+        PieceType::Rook => "R",
+        PieceType::Bishop => "B",
+        PieceType::Knight => "N",
+        PieceType::Pawn => ""
+    }
+}
+
+//Standard algebraic notation for a move that is about to be played on `board`, without the check suffix
+pub(crate) fn to_san(board: &Board, mov: &NotatedMove) -> Result<String, String> {
+    let start = position_from_square(&mov.start_position).ok_or_else(|| format!("Invalid square {}", mov.start_position))?;
+    let end = position_from_square(&mov.end_position).ok_or_else(|| format!("Invalid square {}", mov.end_position))?;
+    let piece = board.get_piece_at(start).ok_or_else(|| format!("No piece on {}", mov.start_position))?;
+
+    let file_distance = (start.get_x() as i8 - end.get_x() as i8).abs();
+
+    if piece.get_type() == PieceType::King && file_distance == 2 {
+        return Ok(if end.get_x() > start.get_x() { "O-O".to_string() } else { "O-O-O".to_string() });
+    }
+
+    let captures = board.get_piece_at(end).is_some() || (piece.get_type() == PieceType::Pawn && file_distance != 0);
+    let mut san = String::new();
+
+    if piece.get_type() == PieceType::Pawn {
+        if captures {
+            san.push_str(&mov.start_position[0..1]);
+        }
+    } else {
+        san.push_str(piece_letter(piece.get_type()));
+        san.push_str(&disambiguation(board, start, end, mov));
+    }
+
+    if captures {
+        san.push('x');
+    }
+    san.push_str(&mov.end_position);
+
+    if let Some(promotes_to) = &mov.promotes_to {
+        san.push('=');
+        san.push_str(promotes_to);
+    }
+
+    Ok(san)
+}
+
+pub(crate) fn check_suffix(state: &schackmotor::GameState) -> &'static str {
+    match state {
+        schackmotor::GameState::Check(_) => "+",
+        schackmotor::GameState::Checkmate(_) => "#",
+        schackmotor::GameState::Normal | schackmotor::GameState::Draw => ""
+    }
+}
+
+//The file, rank or whole square needed to tell the moving piece apart from others of its kind
+fn disambiguation(board: &Board, start: Position, end: Position, mov: &NotatedMove) -> String {
+    let piece = board.get_piece_at(start).unwrap();
+    let rivals: Vec<Position> = board.get_pieces().iter()
+        .filter(|other| other.get_type() == piece.get_type() && other.get_color() == piece.get_color()
+            && other.get_position() != start)
+        .map(|other| other.get_position())
+        .filter(|position| board.get_possible_moves_from_position(*position)
+            .is_some_and(|moves| moves.iter().any(|mov| mov.0 == end)))
+        .collect();
+
+    if rivals.is_empty() {
+        "".to_string()
+    } else if rivals.iter().all(|rival| rival.get_x() != start.get_x()) {
+        mov.start_position[0..1].to_string()
+    } else if rivals.iter().all(|rival| rival.get_y() != start.get_y()) {
+        mov.start_position[1..2].to_string()
+    } else {
+        mov.start_position.clone()
+    }
+}
+
+//Groups the moves in pairs like "1. e4 e5", or "12... Kd7" for a Black move without White's
+pub(crate) fn numbered_rows(history: &[PlayedMove], start_position: &Fen) -> Vec<String> {
+    let first_number = start_position.get_fullmove_number() as usize;
+    let mut slots: Vec<Option<&PlayedMove>> = history.iter().map(Some).collect();
+    if start_position.get_to_move() == schackmotor::Color::Black && !history.is_empty() {
+        slots.insert(0, None);
+    }
+
+    slots.chunks(2).enumerate()
+        .map(|(i, pair)| match pair {
+            [Some(white), Some(black)] => format!("{}. {} {}", first_number + i, white.san, black.san),
+            [None, Some(black)] => format!("{}... {}", first_number + i, black.san),
+            [Some(white)] => format!("{}. {}", first_number + i, white.san),
+            _ => unreachable!()
+        })
+        .collect()
+}
+
+//Finds the move a SAN string like "Nbd7", "exd5", "e8=Q+" or "O-O" describes when `color` is to move on `board`
+pub(crate) fn from_san(board: &Board, san: &str, color: schackmotor::Color) -> Result<NotatedMove, String> {
+    let san = san.trim_end_matches(|c| c == '+' || c == '#' || c == '!' || c == '?');
+
+    if san == "O-O" || san == "0-0" || san == "O-O-O" || san == "0-0-0" {
+        let king = board.get_pieces().into_iter()
+            .find(|piece| piece.get_type() == PieceType::King && piece.get_color() == color)
+            .ok_or_else(|| "There is no king to castle with".to_string())?;
+        let start = king.get_position();
+        let end_x = if san.len() == 3 { start.get_x().checked_add(2) } else { start.get_x().checked_sub(2) }
+            .filter(|x| (1..=8).contains(x))
+            .ok_or_else(|| format!("The king can't castle from {}", start))?;
+        return Ok(NotatedMove::new(start.to_string(), Position::new(end_x, start.get_y()).to_string(), None));
+    }
+
+    let (san, promotes_to) = match san.find('=') {
+        Some(i) => (&san[..i], Some(san[i + 1..].to_string())),
+        None => match san.chars().last() {
+            Some(c) if "QRBN".contains(c) && san.len() > 2 && san.as_bytes()[san.len() - 2].is_ascii_digit() => {
+                (&san[..san.len() - 1], Some(c.to_string()))
+            }
+            _ => (san, None)
+        }
+    };
+
+    let (piece_type, rest) = match san.chars().next() {
+        Some('K') => (PieceType::King, &san[1..]),
+        Some('Q') => (PieceType::Queen, &san[1..]),
+        Some('R') => (PieceType::Rook, &san[1..]),
+        Some('B') => (PieceType::Bishop, &san[1..]),
+        Some('N') => (PieceType::Knight, &san[1..]),
+        _ => (PieceType::Pawn, san)
+    };
+
+    let rest: String = rest.chars().filter(|c| *c != 'x' && *c != '-').collect();
+    if rest.len() < 2 || !rest.is_ascii() {
+        return Err(format!("Can't read move \"{}\"", san));
+    }
+
+    let end_square = &rest[rest.len() - 2..];
+    let end = position_from_square(end_square).ok_or_else(|| format!("Can't read move \"{}\"", san))?;
+    let hint = &rest[..rest.len() - 2];
+
+    let candidates: Vec<Position> = board.get_pieces().into_iter()
+        .filter(|piece| piece.get_type() == piece_type && piece.get_color() == color)
+        .map(|piece| piece.get_position())
+        .filter(|position| {
+            let square = position.to_string();
+            hint.chars().all(|c| square.contains(c))
+        })
+        .filter(|position| board.get_possible_moves_from_position(*position)
+            .is_some_and(|moves| moves.iter().any(|mov| mov.0 == end)))
+        .collect();
+
+    match candidates.as_slice() {
+        [start] => Ok(NotatedMove::new(start.to_string(), end_square.to_string(), promotes_to)),
+        [] => Err(format!("No piece can play \"{}\"", san)),
+        _ => Err(format!("\"{}\" is ambiguous", san))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board_from(fen: &str) -> Board {
+        fen.parse::<Fen>().unwrap().to_board()
+    }
+
+    fn notated(from: &str, to: &str, promotes_to: Option<&str>) -> NotatedMove {
+        NotatedMove::new(from.to_string(), to.to_string(), promotes_to.map(|piece| piece.to_string()))
+    }
+
+    #[test]
+    fn disambiguates_by_file() {
+        let board = board_from("1n2k3/8/5n2/8/8/8/8/4K3 b - - 0 1");
+        assert_eq!(to_san(&board, &notated("b8", "d7", None)).unwrap(), "Nbd7");
+        assert_eq!(to_san(&board, &notated("f6", "d7", None)).unwrap(), "Nfd7");
+        assert_eq!(from_san(&board, "Nbd7", schackmotor::Color::Black).unwrap(), notated("b8", "d7", None));
+        assert!(from_san(&board, "Nd7", schackmotor::Color::Black).is_err());
+    }
+
+    #[test]
+    fn disambiguates_by_rank() {
+        let board = board_from("7k/8/8/8/8/4R3/8/K3R3 w - - 0 1");
+        assert_eq!(to_san(&board, &notated("e1", "e2", None)).unwrap(), "R1e2");
+        assert_eq!(to_san(&board, &notated("e3", "e2", None)).unwrap(), "R3e2");
+        assert_eq!(from_san(&board, "R1e2", schackmotor::Color::White).unwrap(), notated("e1", "e2", None));
+    }
+
+    #[test]
+    fn promotion_with_check() {
+        let mut board = board_from("7k/4P3/8/8/8/8/8/K7 w - - 0 1");
+        let mov = from_san(&board, "e8=Q+", schackmotor::Color::White).unwrap();
+        assert_eq!(mov, notated("e7", "e8", Some("Q")));
+        assert_eq!(from_san(&board, "e8Q", schackmotor::Color::White).unwrap(), mov);
+
+        assert_eq!(to_san(&board, &mov).unwrap(), "e8=Q");
+        board.take_move(mov.to_string()).unwrap();
+        assert_eq!(check_suffix(&board.get_game_state()), "+");
+    }
+
+    #[test]
+    fn castling() {
+        let board = board_from("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1");
+        assert_eq!(to_san(&board, &notated("e1", "g1", None)).unwrap(), "O-O");
+        assert_eq!(to_san(&board, &notated("e1", "c1", None)).unwrap(), "O-O-O");
+        assert_eq!(from_san(&board, "O-O", schackmotor::Color::White).unwrap(), notated("e1", "g1", None));
+        assert_eq!(from_san(&board, "O-O-O", schackmotor::Color::Black).unwrap(), notated("e8", "c8", None));
+    }
+
+    #[test]
+    fn castling_off_the_board_is_an_error() {
+        let board = board_from("4k3/8/8/8/8/8/8/1K5R w - - 0 1");
+        assert!(from_san(&board, "O-O-O", schackmotor::Color::White).is_err());
+
+        let board = board_from("4k3/8/8/8/8/8/8/R6K w - - 0 1");
+        assert!(from_san(&board, "O-O", schackmotor::Color::White).is_err());
+    }
+
+    #[test]
+    fn rows_start_with_blacks_move() {
+        let start: Fen = "4k3/8/8/8/8/8/8/4K3 b - - 0 12".parse().unwrap();
+        let history = vec![
+            PlayedMove { notated: notated("e8", "d7", None), san: "Kd7".to_string() },
+            PlayedMove { notated: notated("e1", "e2", None), san: "Ke2".to_string() },
+            PlayedMove { notated: notated("d7", "d6", None), san: "Kd6".to_string() }
+        ];
+        assert_eq!(numbered_rows(&history, &start), vec!["12... Kd7", "13. Ke2 Kd6"]);
+    }
+}
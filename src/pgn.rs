@@ -0,0 +1,290 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use schackmotor::Color;
+use crate::fen::Fen;
+use crate::notation::PlayedMove;
+
+const LINE_LENGTH: usize = 80;
+
+//The Seven Tag Roster every PGN game starts with, and the starting position if it isn't the standard one
+pub(crate) struct PgnHeaders {
+    pub(crate) event: String,
+    pub(crate) site: String,
+    pub(crate) date: String,
+    pub(crate) round: String,
+    pub(crate) white: String,
+    pub(crate) black: String,
+    pub(crate) result: String,
+    pub(crate) start_position: Option<Fen>
+}
+
+impl PgnHeaders {
+    //The result stays unknown until the game is written out
+    pub(crate) fn new(event: &str, white: String, black: String, round: Option<usize>) -> Self {
+        let (year, month, day, _) = now();
+
+        PgnHeaders {
+            event: event.to_string(),
+            site: "?".to_string(),
+            date: format!("{:04}.{:02}.{:02}", year, month, day),
+            round: round.map_or_else(|| "?".to_string(), |round| round.to_string()),
+            white,
+            black,
+            result: "*".to_string(),
+            start_position: None
+        }
+    }
+}
+
+pub(crate) fn result_tag(game_over: bool, winner: Option<Color>) -> &'static str {
+    match (game_over, winner) {
+        (false, _) => "*",
+        (true, Some(Color::White)) => "1-0",
+        (true, Some(Color::Black)) => "0-1",
+        (true, None) => "1/2-1/2"
+    }
+}
+
+pub(crate) fn write_pgn(headers: &PgnHeaders, history: &[PlayedMove]) -> String {
+    let mut text = String::new();
+
+    for (name, value) in &[("Event", &headers.event), ("Site", &headers.site), ("Date", &headers.date),
+        ("Round", &headers.round), ("White", &headers.white), ("Black", &headers.black), ("Result", &headers.result)] {
+        text.push_str(&format!("[{} \"{}\"]\n", name, value.replace('\\', "\\\\").replace('"', "\\\"")));
+    }
+    if let Some(start_position) = &headers.start_position {
+        text.push_str(&format!("[SetUp \"1\"]\n[FEN \"{}\"]\n", start_position));
+    }
+    text.push('\n');
+
+    let (first_number, offset) = match &headers.start_position {
+        Some(start_position) => (start_position.get_fullmove_number() as usize,
+                                 if start_position.get_to_move() == Color::Black { 1 } else { 0 }),
+        None => (1, 0)
+    };
+
+    let mut tokens = Vec::new();
+    for (i, played) in history.iter().enumerate() {
+        let ply = i + offset;
+        if ply % 2 == 0 {
+            tokens.push(format!("{}.", first_number + ply / 2));
+        } else if i == 0 {
+            tokens.push(format!("{}...", first_number + ply / 2));
+        }
+        tokens.push(played.san.clone());
+    }
+    tokens.push(headers.result.clone());
+
+    let mut line_length = 0;
+    for token in tokens {
+        if line_length > 0 && line_length + 1 + token.len() > LINE_LENGTH {
+            text.push('\n');
+            line_length = 0;
+        } else if line_length > 0 {
+            text.push(' ');
+            line_length += 1;
+        }
+        line_length += token.len();
+        text.push_str(&token);
+    }
+    text.push('\n');
+
+    text
+}
+
+pub(crate) fn save_pgn(directory: &Path, text: &str) -> Result<PathBuf, String> {
+    fs::create_dir_all(directory).map_err(|e| format!("Could not create {}: {}", directory.display(), e))?;
+
+    let (year, month, day, seconds_of_day) = now();
+    let mut path = directory.join(format!("game-{:04}{:02}{:02}-{:02}{:02}{:02}.pgn", year, month, day,
+                                          seconds_of_day / 3600, seconds_of_day / 60 % 60, seconds_of_day % 60));
+    let mut copy = 1;
+    while path.exists() {
+        copy += 1;
+        path = directory.join(format!("game-{:04}{:02}{:02}-{:02}{:02}{:02}-{}.pgn", year, month, day,
+                                      seconds_of_day / 3600, seconds_of_day / 60 % 60, seconds_of_day % 60, copy));
+    }
+
+    fs::write(&path, text).map_err(|e| format!("Could not write {}: {}", path.display(), e))?;
+
+    Ok(path)
+}
+
+//The current UTC date and the seconds since midnight
+fn now() -> (i64, u32, u32, u64) {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    (year, month, day, seconds % 86400)
+}
+
+//Converts days since 1970-01-01 to a proleptic Gregorian calendar date
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = (if z >= 0 { z } else { z - 146_096 }) / 146_097;
+    let day_of_era = z - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = (if month_index < 10 { month_index + 3 } else { month_index - 9 }) as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+//Splits the first game of a PGN file into its tag pairs and the SAN of its moves,
+//dropping comments, variations, annotations, move numbers and the result
+pub(crate) fn read_pgn(text: &str) -> Result<(Vec<(String, String)>, Vec<String>), String> {
+    let mut headers = Vec::new();
+    let mut movetext = String::new();
+
+    for line in text.lines() {
+        let line = line.trim();
+        if line.starts_with('[') && line.ends_with(']') {
+            if !movetext.trim().is_empty() {
+                break;
+            }
+            let tag = &line[1..line.len() - 1];
+            if let Some(space) = tag.find(' ') {
+                //Only the outer quotes, a value may end with an escaped one
+                let value = tag[space..].trim();
+                let value = if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') { &value[1..value.len() - 1] } else { value };
+                headers.push((tag[..space].to_string(), value.replace("\\\"", "\"").replace("\\\\", "\\")));
+            }
+        } else if !line.starts_with('%') {
+            movetext.push_str(line);
+            movetext.push('\n');
+        }
+    }
+
+    let mut moves = Vec::new();
+    let mut token = String::new();
+    let mut variation_depth = 0;
+    let mut chars = movetext.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => {
+                if !chars.by_ref().any(|c| c == '}') {
+                    return Err("Unterminated comment".to_string());
+                }
+            }
+            ';' => {
+                chars.by_ref().find(|c| *c == '\n');
+            }
+            '(' => variation_depth += 1,
+            ')' => {
+                if variation_depth == 0 {
+                    return Err("Unbalanced variation".to_string());
+                }
+                variation_depth -= 1;
+            }
+            c if c.is_whitespace() || c == '.' => {
+                push_san_token(&mut moves, &mut token, variation_depth);
+                continue;
+            }
+            c => {
+                token.push(c);
+                continue;
+            }
+        }
+        push_san_token(&mut moves, &mut token, variation_depth);
+    }
+    push_san_token(&mut moves, &mut token, variation_depth);
+
+    Ok((headers, moves))
+}
+
+fn push_san_token(moves: &mut Vec<String>, token: &mut String, variation_depth: usize) {
+    let is_move_number = token.chars().all(|c| c.is_ascii_digit());
+    let is_result = ["1-0", "0-1", "1/2-1/2", "*"].contains(&token.as_str());
+
+    if variation_depth == 0 && !token.is_empty() && !is_move_number && !is_result && !token.starts_with('$') {
+        moves.push(token.clone());
+    }
+    token.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NotatedMove;
+
+    fn played(from: &str, to: &str, san: &str) -> PlayedMove {
+        PlayedMove { notated: NotatedMove::new(from.to_string(), to.to_string(), None), san: san.to_string() }
+    }
+
+    fn headers(start_position: Option<Fen>) -> PgnHeaders {
+        PgnHeaders {
+            event: "Test \"game\"".to_string(),
+            site: "?".to_string(),
+            date: "2020.01.02".to_string(),
+            round: "1".to_string(),
+            white: "a".to_string(),
+            black: "b".to_string(),
+            result: "1-0".to_string(),
+            start_position
+        }
+    }
+
+    #[test]
+    fn writes_tags_and_numbered_moves() {
+        let history = vec![played("e2", "e4", "e4"), played("e7", "e5", "e5"), played("g1", "f3", "Nf3")];
+        assert_eq!(write_pgn(&headers(None), &history),
+                   "[Event \"Test \\\"game\\\"\"]\n[Site \"?\"]\n[Date \"2020.01.02\"]\n[Round \"1\"]\n\
+                    [White \"a\"]\n[Black \"b\"]\n[Result \"1-0\"]\n\n1. e4 e5 2. Nf3 1-0\n");
+    }
+
+    #[test]
+    fn writes_games_starting_with_black() {
+        let start: Fen = "4k3/8/8/8/8/8/8/4K3 b - - 0 12".parse().unwrap();
+        let history = vec![played("e8", "d7", "Kd7"), played("e1", "e2", "Ke2")];
+        let text = write_pgn(&headers(Some(start)), &history);
+        assert!(text.contains("[SetUp \"1\"]\n[FEN \"4k3/8/8/8/8/8/8/4K3 b - - 0 12\"]\n"));
+        assert!(text.ends_with("\n12... Kd7 13. Ke2 1-0\n"));
+    }
+
+    #[test]
+    fn wraps_long_movetext() {
+        let history: Vec<PlayedMove> = (0..60).map(|_| played("g1", "f3", "Nf3")).collect();
+        let text = write_pgn(&headers(None), &history);
+        assert!(text.lines().all(|line| line.len() <= LINE_LENGTH));
+    }
+
+    #[test]
+    fn reads_moves_without_comments_variations_and_nags() {
+        let text = "[Event \"Casual \\\"blitz\\\"\"]\n[White \"a\"]\n[Black \"b\"]\n\n\
+                    % an escaped line\n\
+                    1. e4 {best by test} e5 (1... c5 2. Nf3 (2. c3) d6) 2. Nf3 $1 Nc6 ; the rest of the line\n\
+                    3. Bb5 a6!? 4. Ba4 Nf6 1/2-1/2\n";
+        let (headers, moves) = read_pgn(text).unwrap();
+        assert_eq!(headers[0], ("Event".to_string(), "Casual \"blitz\"".to_string()));
+        assert_eq!(headers.len(), 3);
+        assert_eq!(moves, vec!["e4", "e5", "Nf3", "Nc6", "Bb5", "a6!?", "Ba4", "Nf6"]);
+    }
+
+    #[test]
+    fn reads_only_the_first_game() {
+        let text = "[Event \"1\"]\n\n1. d4 d5 *\n\n[Event \"2\"]\n\n1. c4 *\n";
+        let (headers, moves) = read_pgn(text).unwrap();
+        assert_eq!(headers, vec![("Event".to_string(), "1".to_string())]);
+        assert_eq!(moves, vec!["d4", "d5"]);
+    }
+
+    #[test]
+    fn reads_what_it_writes() {
+        let start: Fen = "4k3/8/8/8/8/8/8/4K3 b - - 0 12".parse().unwrap();
+        let history = vec![played("e8", "d7", "Kd7"), played("e1", "e2", "Ke2")];
+        let (headers, moves) = read_pgn(&write_pgn(&headers(Some(start)), &history)).unwrap();
+        assert_eq!(headers[0], ("Event".to_string(), "Test \"game\"".to_string()));
+        assert!(headers.contains(&("FEN".to_string(), "4k3/8/8/8/8/8/8/4K3 b - - 0 12".to_string())));
+        assert_eq!(moves, vec!["Kd7", "Ke2"]);
+    }
+
+    #[test]
+    fn rejects_broken_movetext() {
+        assert!(read_pgn("1. e4 {never closed").is_err());
+        assert!(read_pgn("1. e4 e5) 2. Nf3").is_err());
+    }
+}
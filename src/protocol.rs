@@ -0,0 +1,393 @@
+use serde::{Deserialize, Serialize};
+use crate::NotatedMove;
+use crate::fen::Fen;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum PlayerColor {
+    White,
+    Black
+}
+
+//The color the proposing player wants to play
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ColorChoice {
+    White,
+    Black,
+    Random
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum DeclineReason {
+    InGame,
+    ColorTaken,
+    Crossed,
+    Malformed
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct TimeControl {
+    pub(crate) base_seconds: u64,
+    pub(crate) increment_seconds: u64
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ClockState {
+    pub(crate) white_ms: u64,
+    pub(crate) black_ms: u64
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct StartGameRequest {
+    pub(crate) color: ColorChoice,
+    pub(crate) nonce: u64,
+    //The proposer's time control is used for the game, no clocks if it has none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) time_control: Option<TimeControl>,
+    //The position the proposer wants to start from, the standard one if there is none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) fen: Option<String>
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct StartGameResponse {
+    pub(crate) accepted: bool,
+    //The color assigned to the proposing player
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) color: Option<PlayerColor>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) reason: Option<DeclineReason>
+}
+
+impl StartGameResponse {
+    pub(crate) fn accepted(color: PlayerColor) -> Self {
+        StartGameResponse { accepted: true, color: Some(color), reason: None }
+    }
+
+    pub(crate) fn declined(reason: DeclineReason) -> Self {
+        StartGameResponse { accepted: false, color: None, reason: Some(reason) }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct MoveRequest {
+    pub(crate) start_square: String,
+    pub(crate) end_square: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) promotes_to: Option<String>,
+    //Both clocks as the mover saw them right after the move
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) clock: Option<ClockState>
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct MoveResponse {
+    pub(crate) valid_move: bool
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum DrawAction {
+    #[default]
+    Offer,
+    Accept,
+    Decline,
+    Withdraw
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub(crate) struct DrawRequest {
+    #[serde(default)]
+    pub(crate) action: DrawAction
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DrawResponse {
+    pub(crate) draw_accepted: bool
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub(crate) struct RematchRequest {}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct RematchResponse {
+    pub(crate) rematch_accepted: bool
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub(crate) struct ResignRequest {}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ResignResponse {
+    pub(crate) acknowledged: bool
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ErrorResponse {
+    pub(crate) error: String
+}
+
+//Every message a peer can POST to us, tagged by the endpoint it arrived on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Message {
+    StartGame(StartGameRequest),
+    Move(MoveRequest),
+    RequestDraw(DrawRequest),
+    RequestRematch(RematchRequest),
+    Resign(ResignRequest)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ProtocolError {
+    UnknownEndpoint(String),
+    MalformedBody(String),
+    InvalidField(String)
+}
+
+impl ProtocolError {
+    pub(crate) fn status_code(&self) -> u16 {
+        match self {
+            ProtocolError::UnknownEndpoint(_) => 404,
+            ProtocolError::MalformedBody(_) | ProtocolError::InvalidField(_) => 400
+        }
+    }
+
+    pub(crate) fn to_response(&self) -> ErrorResponse {
+        ErrorResponse { error: self.to_string() }
+    }
+}
+
+impl std::fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProtocolError::UnknownEndpoint(url) => write!(f, "unknown endpoint {}", url),
+            ProtocolError::MalformedBody(e) => write!(f, "malformed body: {}", e),
+            ProtocolError::InvalidField(e) => write!(f, "invalid field: {}", e)
+        }
+    }
+}
+
+impl Message {
+    pub(crate) fn parse(url: &str, body: &str) -> Result<Message, ProtocolError> {
+        let message = match url {
+            "/start-game" => Message::StartGame(from_body(body)?),
+            "/move" => Message::Move(from_body(body)?),
+            "/request-draw" => Message::RequestDraw(from_body_or_default(body)?),
+            "/request-rematch" => Message::RequestRematch(from_body_or_default(body)?),
+            "/resign" => Message::Resign(from_body_or_default(body)?),
+            _ => return Err(ProtocolError::UnknownEndpoint(url.to_string()))
+        };
+
+        message.validate()?;
+
+        Ok(message)
+    }
+
+    fn validate(&self) -> Result<(), ProtocolError> {
+        match self {
+            Message::Move(request) => {
+                if !is_square(&request.start_square) {
+                    return Err(ProtocolError::InvalidField(format!("start_square \"{}\"", request.start_square)));
+                }
+                if !is_square(&request.end_square) {
+                    return Err(ProtocolError::InvalidField(format!("end_square \"{}\"", request.end_square)));
+                }
+                if let Some(promotes_to) = &request.promotes_to {
+                    if !["Q", "R", "B", "N"].contains(&promotes_to.as_str()) {
+                        return Err(ProtocolError::InvalidField(format!("promotes_to \"{}\"", promotes_to)));
+                    }
+                }
+            }
+            Message::StartGame(request) => {
+                if let Some(fen) = &request.fen {
+                    fen.parse::<Fen>().map_err(|e| ProtocolError::InvalidField(format!("fen: {}", e)))?;
+                }
+            }
+            Message::RequestDraw(_) | Message::RequestRematch(_) | Message::Resign(_) => {}
+        }
+
+        Ok(())
+    }
+}
+
+impl From<PlayerColor> for schackmotor::Color {
+    fn from(color: PlayerColor) -> Self {
+        match color {
+            PlayerColor::White => schackmotor::Color::White,
+            PlayerColor::Black => schackmotor::Color::Black
+        }
+    }
+}
+
+impl std::str::FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "white" => Ok(ColorChoice::White),
+            "black" => Ok(ColorChoice::Black),
+            "random" => Ok(ColorChoice::Random),
+            _ => Err(format!("invalid color \"{}\", expected white, black or random", s))
+        }
+    }
+}
+
+impl TimeControl {
+    pub(crate) fn base(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.base_seconds)
+    }
+
+    pub(crate) fn increment(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.increment_seconds)
+    }
+}
+
+//Reads time controls written the usual way, minutes plus increment in seconds, e.g. "5+3"
+impl std::str::FromStr for TimeControl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || format!("invalid time control \"{}\", expected minutes+increment like 5+3", s);
+        let mut parts = s.trim().splitn(2, '+');
+
+        let minutes = parts.next().unwrap_or("").trim().parse::<f64>().map_err(|_| error())?;
+        let increment_seconds = match parts.next() {
+            Some(increment) => increment.trim().parse::<u64>().map_err(|_| error())?,
+            None => 0
+        };
+
+        if minutes.is_nan() || minutes <= 0.0 {
+            return Err(error());
+        }
+
+        Ok(TimeControl { base_seconds: (minutes * 60.0).round() as u64, increment_seconds })
+    }
+}
+
+impl std::fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.base_seconds % 60 == 0 {
+            write!(f, "{}+{}", self.base_seconds / 60, self.increment_seconds)
+        } else {
+            write!(f, "{}+{}", self.base_seconds as f64 / 60.0, self.increment_seconds)
+        }
+    }
+}
+
+impl std::fmt::Display for DeclineReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeclineReason::InGame => write!(f, "already in a game"),
+            DeclineReason::ColorTaken => write!(f, "same color"),
+            DeclineReason::Crossed => write!(f, "proposals crossed"),
+            DeclineReason::Malformed => write!(f, "invalid answer")
+        }
+    }
+}
+
+impl From<&NotatedMove> for MoveRequest {
+    fn from(mov: &NotatedMove) -> Self {
+        MoveRequest {
+            start_square: mov.start_position.clone(),
+            end_square: mov.end_position.clone(),
+            promotes_to: mov.promotes_to.clone(),
+            clock: None
+        }
+    }
+}
+
+impl From<MoveRequest> for NotatedMove {
+    fn from(request: MoveRequest) -> Self {
+        NotatedMove::new(request.start_square, request.end_square, request.promotes_to)
+    }
+}
+
+pub(crate) fn is_square(square: &str) -> bool {
+    let bytes = square.as_bytes();
+    bytes.len() == 2 && (b'a'..=b'h').contains(&bytes[0]) && (b'1'..=b'8').contains(&bytes[1])
+}
+
+fn from_body<T: serde::de::DeserializeOwned>(body: &str) -> Result<T, ProtocolError> {
+    serde_json::from_str(body).map_err(|e| ProtocolError::MalformedBody(e.to_string()))
+}
+
+fn from_body_or_default<T: serde::de::DeserializeOwned + Default>(body: &str) -> Result<T, ProtocolError> {
+    if body.trim().is_empty() {
+        Ok(T::default())
+    } else {
+        from_body(body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_status(url: &str, body: &str) -> u16 {
+        Message::parse(url, body).unwrap_err().status_code()
+    }
+
+    #[test]
+    fn parses_every_endpoint() {
+        assert_eq!(Message::parse("/move", r#"{"start_square": "e7", "end_square": "e8", "promotes_to": "Q"}"#).unwrap(),
+                   Message::Move(MoveRequest { start_square: "e7".to_string(), end_square: "e8".to_string(),
+                                               promotes_to: Some("Q".to_string()), clock: None }));
+        assert_eq!(Message::parse("/start-game", r#"{"color": "white", "nonce": 3}"#).unwrap(),
+                   Message::StartGame(StartGameRequest { color: ColorChoice::White, nonce: 3, time_control: None, fen: None }));
+        assert_eq!(Message::parse("/request-draw", r#"{"action": "withdraw"}"#).unwrap(),
+                   Message::RequestDraw(DrawRequest { action: DrawAction::Withdraw }));
+    }
+
+    #[test]
+    fn empty_bodies_mean_the_defaults() {
+        assert_eq!(Message::parse("/request-draw", "").unwrap(), Message::RequestDraw(DrawRequest { action: DrawAction::Offer }));
+        assert_eq!(Message::parse("/request-draw", "{}").unwrap(), Message::RequestDraw(DrawRequest { action: DrawAction::Offer }));
+        assert_eq!(Message::parse("/request-rematch", "").unwrap(), Message::RequestRematch(RematchRequest {}));
+        assert_eq!(Message::parse("/resign", " ").unwrap(), Message::Resign(ResignRequest {}));
+    }
+
+    #[test]
+    fn unknown_endpoints_are_not_found() {
+        assert_eq!(Message::parse("/castle", "{}"), Err(ProtocolError::UnknownEndpoint("/castle".to_string())));
+        assert_eq!(error_status("/castle", "{}"), 404);
+    }
+
+    #[test]
+    fn malformed_bodies_are_bad_requests() {
+        for (url, body) in &[("/move", "{\"start_square\": \"e2\""), ("/move", "e2e4"), ("/move", "{\"start_square\": \"e2\"}"),
+                             ("/start-game", "{\"color\": \"green\", \"nonce\": 1}"), ("/start-game", ""),
+                             ("/request-draw", "{\"action\": \"maybe\"}")] {
+            match Message::parse(url, body) {
+                Err(ProtocolError::MalformedBody(_)) => {}
+                other => panic!("{} {} gave {:?}", url, body, other)
+            }
+            assert_eq!(error_status(url, body), 400);
+        }
+    }
+
+    #[test]
+    fn invalid_fields_are_bad_requests() {
+        for body in &[r#"{"start_square": "i2", "end_square": "e4"}"#, r#"{"start_square": "e2", "end_square": "e9"}"#,
+                      r#"{"start_square": "E2", "end_square": "e4"}"#, r#"{"start_square": "e7", "end_square": "e8", "promotes_to": "K"}"#,
+                      r#"{"start_square": "e7", "end_square": "e8", "promotes_to": "q"}"#] {
+            match Message::parse("/move", body) {
+                Err(ProtocolError::InvalidField(_)) => {}
+                other => panic!("{} gave {:?}", body, other)
+            }
+            assert_eq!(error_status("/move", body), 400);
+        }
+
+        match Message::parse("/start-game", r#"{"color": "white", "nonce": 1, "fen": "8/8/8/8/8/8/8/8 w - - 0 1"}"#) {
+            Err(ProtocolError::InvalidField(_)) => {}
+            other => panic!("a position without kings gave {:?}", other)
+        }
+    }
+
+    #[test]
+    fn errors_are_sent_as_json() {
+        let response = ProtocolError::InvalidField("end_square \"e9\"".to_string()).to_response();
+        assert_eq!(serde_json::to_string(&response).unwrap(), r#"{"error":"invalid field: end_square \"e9\""}"#);
+    }
+}
@@ -0,0 +1,161 @@
+use std::fs;
+use std::path::Path;
+use ggez::event::{self, KeyCode, MouseButton};
+use ggez::{graphics, Context, GameResult};
+use crate::{DataHandler, GraphicsHandler};
+use crate::config::Config;
+use crate::fen::Fen;
+use crate::notation::{self, PlayedMove};
+use crate::pgn;
+use crate::settings::Settings;
+use crate::theme::{MenuChange, ThemeMenu};
+
+//Steps through a game loaded from a PGN file instead of playing one
+pub(crate) struct ReplayState {
+    data_handler: DataHandler,
+    graphics_handler: GraphicsHandler,
+    moves: Vec<PlayedMove>,
+    current: usize, //number of moves applied to the board
+    theme_menu: ThemeMenu,
+    settings: Settings
+}
+
+impl ReplayState {
+    pub(crate) fn new(ctx: &mut Context, path: &Path, config: &Config) -> GameResult<ReplayState> {
+        let (headers, start_position, moves) = load(path).map_err(ggez::GameError::ConfigError)?;
+
+        let header = |name: &str| headers.iter().find(|(key, _)| key == name).map_or("?", |(_, value)| value.as_str());
+        graphics::set_window_title(ctx, &format!("Schack - {} vs {} ({})", header("White"), header("Black"), header("Result")));
+
+        let mut data_handler = DataHandler::new(start_position);
+        data_handler.history = moves.clone();
+        let theme_menu = ThemeMenu::load(ctx, &config.board_theme, &config.piece_set);
+        let graphics_handler = GraphicsHandler::new(&data_handler, config.animation_duration,
+                                                    theme_menu.get_board_theme(), theme_menu.get_piece_set(), ctx);
+
+        let mut state = ReplayState {
+            data_handler,
+            graphics_handler,
+            moves,
+            current: 0,
+            theme_menu,
+            settings: config.settings.clone()
+        };
+        state.go_to(0);
+
+        Ok(state)
+    }
+
+    fn go_to(&mut self, ply: usize) {
+        let ply = ply.min(self.moves.len());
+
+        //Moves were checked when the file was loaded, so replaying them again can't fail
+        let mut board = self.data_handler.start_position.to_board();
+        for played in &self.moves[..ply] {
+            board.take_move(played.notated.to_string()).unwrap();
+        }
+
+        self.data_handler.board = board;
+        self.data_handler.update_game_state();
+        self.current = ply;
+
+        self.graphics_handler.shown_moves = Some(ply);
+        self.graphics_handler.update_board(&self.data_handler);
+        if let Some(index) = ply.checked_sub(1) {
+            self.graphics_handler.scroll_to_move(index, &self.data_handler);
+        }
+    }
+}
+
+//Reads a PGN file and plays every move through the engine from the position in its FEN tag, if it has one,
+//reporting the first illegal move
+pub(crate) fn load(path: &Path) -> Result<(Vec<(String, String)>, Fen, Vec<PlayedMove>), String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    let (headers, sans) = pgn::read_pgn(&text)?;
+
+    let start_position = match headers.iter().find(|(key, _)| key == "FEN") {
+        Some((_, fen)) => fen.parse::<Fen>().map_err(|e| format!("{} in {}", e, path.display()))?,
+        None => Fen::standard()
+    };
+
+    let mut board = start_position.to_board();
+    let mut color = start_position.get_to_move();
+    let mut moves = Vec::new();
+    let offset = if color == schackmotor::Color::Black { 1 } else { 0 };
+
+    for (i, san) in sans.iter().enumerate() {
+        let ply = i + offset;
+        let move_number = format!("{}{}", start_position.get_fullmove_number() as usize + ply / 2,
+                                  if ply % 2 == 0 { "." } else { "..." });
+        let illegal = |e: String| format!("Illegal move {} {} in {}: {}", move_number, san, path.display(), e);
+
+        let notated = notation::from_san(&board, san, color).map_err(illegal)?;
+        let played_san = notation::to_san(&board, &notated).map_err(illegal)?;
+        board.take_move(notated.to_string()).map_err(illegal)?;
+
+        moves.push(PlayedMove { san: played_san + notation::check_suffix(&board.get_game_state()), notated });
+        color = color.invert();
+    }
+
+    Ok((headers, start_position, moves))
+}
+
+impl event::EventHandler for ReplayState {
+    fn update(&mut self, ctx: &mut Context) -> GameResult {
+        self.graphics_handler.update(&mut self.data_handler, ctx);
+        Ok(())
+    }
+
+    fn draw(&mut self, ctx: &mut Context) -> GameResult {
+        self.graphics_handler.draw(&self.data_handler, None, None, ctx)?;
+
+        if self.theme_menu.is_open() {
+            let (lines, selected) = self.theme_menu.lines();
+            self.graphics_handler.draw_menu(ctx, &lines, selected)?;
+        }
+
+        graphics::present(ctx)?;
+
+        Ok(())
+    }
+
+    fn mouse_button_up_event(&mut self, _ctx: &mut Context, button: MouseButton, x: f32, y: f32) {
+        if button == MouseButton::Left {
+            if let Some(index) = self.graphics_handler.history_index_at(x, y, &self.data_handler) {
+                self.go_to(index + 1);
+            }
+        }
+    }
+
+    fn mouse_wheel_event(&mut self, _ctx: &mut Context, _x: f32, y: f32) {
+        self.graphics_handler.scroll_history(y, &self.data_handler);
+    }
+
+    fn resize_event(&mut self, ctx: &mut Context, width: f32, height: f32) {
+        self.graphics_handler.resize(ctx, width, height);
+    }
+
+    fn key_down_event(&mut self, ctx: &mut Context, keycode: KeyCode, _keymod: ggez::event::KeyMods, _repeat: bool) {
+        if self.theme_menu.is_open() {
+            let change = self.theme_menu.key_pressed(keycode);
+            self.graphics_handler.theme_changed(ctx, &self.theme_menu, change);
+            if change != MenuChange::None {
+                self.settings.save_themes(&self.theme_menu);
+            }
+            return;
+        }
+
+        match keycode {
+            KeyCode::Left => self.go_to(self.current.saturating_sub(1)),
+            KeyCode::Right => self.go_to(self.current + 1),
+            KeyCode::Home | KeyCode::Up => self.go_to(0),
+            KeyCode::End | KeyCode::Down => self.go_to(self.moves.len()),
+            KeyCode::O => self.graphics_handler.flipped = !self.graphics_handler.flipped,
+            KeyCode::Space => self.graphics_handler.skip_animation(),
+            KeyCode::F5 => self.graphics_handler.reload_sprites(ctx),
+            KeyCode::T => self.theme_menu.toggle(),
+            KeyCode::C => self.graphics_handler.show_coordinates = !self.graphics_handler.show_coordinates,
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,224 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use crate::protocol::{ColorChoice, TimeControl};
+use crate::theme::{ThemeMenu, DEFAULT_BOARD_THEME, DEFAULT_PIECE_SET};
+
+const SETTINGS_FILE: &str = "settings.json";
+const MAX_ANIMATION_MS: u64 = 5000;
+
+//Preferences kept between runs in settings.json in the user's config directory. They are the defaults
+//for the command line options and environment variables, which still take precedence.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct Settings {
+    pub(crate) bind_address: String,
+    pub(crate) listen_port: u16,
+    pub(crate) peer_address: String, //empty when there is no usual opponent
+    pub(crate) color: String, //white, black or random
+    pub(crate) time_control: String, //minutes+increment like 5+3, empty for no clock
+    pub(crate) pgn_directory: String,
+    pub(crate) board_theme: String,
+    pub(crate) piece_set: String,
+    pub(crate) sound: bool, //the client plays no sounds yet, this is kept for when it does
+    pub(crate) flip: bool, //turn the board towards the player to move in local games
+    pub(crate) flip_board: bool, //turn the board the other way around from where it would normally face
+    pub(crate) confirm_moves: bool,
+    pub(crate) always_queen: bool,
+    pub(crate) animation_ms: u64
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            bind_address: "0.0.0.0".to_string(),
+            listen_port: 7878,
+            peer_address: String::new(),
+            color: "random".to_string(),
+            time_control: String::new(),
+            pgn_directory: "games".to_string(),
+            board_theme: DEFAULT_BOARD_THEME.to_string(),
+            piece_set: DEFAULT_PIECE_SET.to_string(),
+            sound: true,
+            flip: false,
+            flip_board: false,
+            confirm_moves: false,
+            always_queen: false,
+            animation_ms: 200
+        }
+    }
+}
+
+impl Settings {
+    //Reads the settings file, writing one with the defaults if there is none. A file that can't be read
+    //is reported and left alone for the user to fix, and the defaults are used instead.
+    pub(crate) fn load() -> Settings {
+        let path = match settings_path() {
+            Some(path) => path,
+            None => return Settings::default()
+        };
+
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(_) if !path.exists() => {
+                let settings = Settings::default();
+                if let Err(e) = settings.save() {
+                    println!("{}", e);
+                }
+                return settings;
+            }
+            Err(e) => {
+                println!("Could not read {}, using the default settings: {}", path.display(), e);
+                return Settings::default();
+            }
+        };
+
+        match serde_json::from_str::<Settings>(&text) {
+            Ok(mut settings) => {
+                for problem in settings.validate() {
+                    println!("{} in {}, using the default instead", problem, path.display());
+                }
+                settings
+            }
+            Err(e) => {
+                println!("Could not read {}, using the default settings: {}", path.display(), e);
+                Settings::default()
+            }
+        }
+    }
+
+    pub(crate) fn save(&self) -> Result<(), String> {
+        let path = settings_path().ok_or_else(|| "No config directory to save the settings in".to_string())?;
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory).map_err(|e| format!("Could not create {}: {}", directory.display(), e))?;
+        }
+
+        let text = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(&path, text).map_err(|e| format!("Could not write {}: {}", path.display(), e))
+    }
+
+    //Keeps the board theme and piece set picked in the menu for the next run
+    pub(crate) fn save_themes(&mut self, theme_menu: &ThemeMenu) {
+        self.board_theme = theme_menu.get_board_theme().id.clone();
+        self.piece_set = theme_menu.get_piece_set().id.clone();
+        if let Err(e) = self.save() {
+            println!("{}", e);
+        }
+    }
+
+    //Puts the default back in place of every invalid value and describes what was wrong
+    fn validate(&mut self) -> Vec<String> {
+        let defaults = Settings::default();
+        let mut problems = Vec::new();
+
+        if self.bind_address.trim().is_empty() {
+            problems.push("Empty bind address".to_string());
+            self.bind_address = defaults.bind_address;
+        }
+        if self.listen_port == 0 {
+            problems.push("Invalid port 0".to_string());
+            self.listen_port = defaults.listen_port;
+        }
+        if let Err(e) = self.color.parse::<ColorChoice>() {
+            problems.push(e);
+            self.color = defaults.color;
+        }
+        if let Err(e) = self.get_time_control() {
+            problems.push(e);
+            self.time_control = defaults.time_control;
+        }
+        if self.pgn_directory.trim().is_empty() {
+            problems.push("Empty PGN directory".to_string());
+            self.pgn_directory = defaults.pgn_directory;
+        }
+        if self.board_theme.trim().is_empty() {
+            problems.push("Empty board theme".to_string());
+            self.board_theme = defaults.board_theme;
+        }
+        if self.piece_set.trim().is_empty() {
+            problems.push("Empty piece set".to_string());
+            self.piece_set = defaults.piece_set;
+        }
+        if self.animation_ms > MAX_ANIMATION_MS {
+            problems.push(format!("Animation of {} ms is longer than {} ms", self.animation_ms, MAX_ANIMATION_MS));
+            self.animation_ms = defaults.animation_ms;
+        }
+
+        problems
+    }
+
+    pub(crate) fn get_time_control(&self) -> Result<Option<TimeControl>, String> {
+        if self.time_control.trim().is_empty() {
+            Ok(None)
+        } else {
+            self.time_control.parse::<TimeControl>().map(Some)
+        }
+    }
+}
+
+//settings.json in the schack directory of the platform's config directory
+fn settings_path() -> Option<PathBuf> {
+    let config_directory = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|home| PathBuf::from(home).join("Library").join("Application Support"))
+    } else {
+        env::var_os("XDG_CONFIG_HOME").map(PathBuf::from).filter(|path| path.is_absolute())
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+    };
+
+    config_directory.map(|directory| directory.join("schack").join(SETTINGS_FILE))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_settings_get_their_defaults() {
+        let settings: Settings = serde_json::from_str(r#"{"listen_port": 9000, "confirm_moves": true}"#).unwrap();
+        assert_eq!(settings, Settings { listen_port: 9000, confirm_moves: true, ..Settings::default() });
+    }
+
+    #[test]
+    fn corrupt_files_are_not_read() {
+        assert!(serde_json::from_str::<Settings>("{\"listen_port\": 9000").is_err());
+        assert!(serde_json::from_str::<Settings>(r#"{"listen_port": "many"}"#).is_err());
+        assert!(serde_json::from_str::<Settings>(r#"{"listen_port": 70000}"#).is_err());
+    }
+
+    #[test]
+    fn invalid_settings_are_replaced_by_defaults() {
+        let mut settings = Settings {
+            bind_address: " ".to_string(),
+            listen_port: 0,
+            color: "purple".to_string(),
+            time_control: "5 minutes".to_string(),
+            pgn_directory: String::new(),
+            board_theme: String::new(),
+            piece_set: String::new(),
+            animation_ms: MAX_ANIMATION_MS + 1,
+            confirm_moves: true,
+            ..Settings::default()
+        };
+
+        assert_eq!(settings.validate().len(), 8);
+        assert_eq!(settings, Settings { confirm_moves: true, ..Settings::default() });
+    }
+
+    #[test]
+    fn valid_settings_are_kept() {
+        let mut settings = Settings {
+            color: "black".to_string(),
+            time_control: "5+3".to_string(),
+            animation_ms: 0,
+            ..Settings::default()
+        };
+        let expected = settings.clone();
+
+        assert!(settings.validate().is_empty());
+        assert_eq!(settings, expected);
+        assert_eq!(settings.get_time_control(), Ok(Some(TimeControl { base_seconds: 300, increment_seconds: 3 })));
+    }
+}
@@ -0,0 +1,64 @@
+use ggez::{graphics, Context};
+use schackmotor::{Color, PieceType};
+
+const PIECE_TYPES: [PieceType; 6] = [PieceType::King, PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight, PieceType::Pawn];
+
+//The images of a piece set, read once from a resource directory and shared by every piece drawn with them.
+//Pieces whose image is missing or broken have no sprite and are drawn as letters instead.
+pub(crate) struct PieceSprites {
+    directory: String, //resource path the images are read from, like "/" or "/pieces/classic"
+    sprites: Vec<((Color, PieceType), Option<graphics::Image>)>
+}
+
+impl PieceSprites {
+    pub(crate) fn load(ctx: &mut Context, directory: &str) -> Self {
+        let mut sprites = Vec::new();
+        for color in &[Color::Black, Color::White] {
+            for piece_type in PIECE_TYPES.iter() {
+                let path = format!("{}/{}_{}.png", directory.trim_end_matches('/'), color_name(*color), piece_type_name(*piece_type));
+                let sprite = match graphics::Image::new(ctx, &path) {
+                    Ok(sprite) => Some(sprite),
+                    Err(e) => {
+                        println!("Could not load {}, drawing a letter instead: {}", path, e);
+                        None
+                    }
+                };
+                sprites.push(((*color, *piece_type), sprite));
+            }
+        }
+
+        PieceSprites {
+            directory: directory.to_string(),
+            sprites
+        }
+    }
+
+    //Reads the images again, for a piece set that was changed on disk
+    pub(crate) fn reload(&mut self, ctx: &mut Context) {
+        *self = PieceSprites::load(ctx, &self.directory);
+    }
+
+    pub(crate) fn get(&self, color: Color, piece_type: PieceType) -> Option<graphics::Image> {
+        self.sprites.iter()
+            .find(|((sprite_color, sprite_type), _)| *sprite_color == color && *sprite_type == piece_type)
+            .and_then(|(_, sprite)| sprite.clone())
+    }
+}
+
+fn color_name(color: Color) -> &'static str {
+    match color {
+        Color::White => "white",
+        Color::Black => "black"
+    }
+}
+
+fn piece_type_name(piece_type: PieceType) -> &'static str {
+    match piece_type {
+        PieceType::King => "king",
+        PieceType::Queen => "queen",
+        PieceType::Rook => "rook",
+        PieceType::Bishop => "bishop",
+        PieceType::Knight => "knight",
+        PieceType::Pawn => "pawn"
+    }
+}
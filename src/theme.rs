@@ -0,0 +1,218 @@
+use std::io::Read;
+use std::path::Path;
+use ggez::event::KeyCode;
+use ggez::{filesystem, Context};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+
+pub(crate) const DEFAULT_BOARD_THEME: &str = "classic";
+pub(crate) const DEFAULT_PIECE_SET: &str = "classic";
+
+const BOARD_THEME_DIRECTORY: &str = "/boards";
+const PIECE_SET_DIRECTORY: &str = "/pieces";
+const PIECE_SET_MANIFEST: &str = "pieces.json";
+
+//The colors of the board, read from a manifest like resources/boards/classic.json. Colors are RGB from 0 to 1.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub(crate) struct BoardTheme {
+    #[serde(skip)]
+    pub(crate) id: String, //the file name of the manifest without .json
+    pub(crate) name: String,
+    pub(crate) light: [f32; 3],
+    pub(crate) dark: [f32; 3],
+    #[serde(default = "default_background")]
+    pub(crate) background: [f32; 3] //around the board and behind the side panel
+}
+
+impl BoardTheme {
+    //Used when no manifest can be read at all
+    fn classic() -> Self {
+        BoardTheme {
+            id: DEFAULT_BOARD_THEME.to_string(),
+            name: "Classic".to_string(),
+            light: [1.0, 0.81, 0.62],
+            dark: [0.82, 0.55, 0.28],
+            background: default_background()
+        }
+    }
+
+    pub(crate) fn light_color(&self) -> ggez::graphics::Color {
+        [self.light[0], self.light[1], self.light[2], 1.0].into()
+    }
+
+    pub(crate) fn dark_color(&self) -> ggez::graphics::Color {
+        [self.dark[0], self.dark[1], self.dark[2], 1.0].into()
+    }
+
+    pub(crate) fn background_color(&self) -> ggez::graphics::Color {
+        [self.background[0], self.background[1], self.background[2], 1.0].into()
+    }
+}
+
+fn default_background() -> [f32; 3] {
+    [0.5, 0.5, 0.5]
+}
+
+//A directory of piece images under resources/pieces, named like white_king.png, with a pieces.json
+//manifest naming the set
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub(crate) struct PieceSet {
+    #[serde(skip)]
+    pub(crate) id: String, //the name of the directory
+    #[serde(skip)]
+    pub(crate) directory: String, //resource path of the images
+    pub(crate) name: String
+}
+
+impl PieceSet {
+    fn classic() -> Self {
+        PieceSet {
+            id: DEFAULT_PIECE_SET.to_string(),
+            directory: format!("{}/{}", PIECE_SET_DIRECTORY, DEFAULT_PIECE_SET),
+            name: "Classic".to_string()
+        }
+    }
+}
+
+fn read_manifest<T: DeserializeOwned>(ctx: &mut Context, path: &Path) -> Result<T, String> {
+    let mut text = String::new();
+    filesystem::open(ctx, path)
+        .map_err(|e| e.to_string())
+        .and_then(|mut file| file.read_to_string(&mut text).map_err(|e| e.to_string()))?;
+    serde_json::from_str(&text).map_err(|e| e.to_string())
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem().map_or_else(String::new, |stem| stem.to_string_lossy().to_string())
+}
+
+//Every board theme with a readable manifest, sorted by name. Broken manifests are reported and skipped.
+pub(crate) fn board_themes(ctx: &mut Context) -> Vec<BoardTheme> {
+    let paths: Vec<_> = filesystem::read_dir(ctx, BOARD_THEME_DIRECTORY).map(|paths| paths.collect()).unwrap_or_default();
+
+    let mut themes = Vec::new();
+    for path in paths.iter().filter(|path| path.extension().is_some_and(|extension| extension == "json")) {
+        match read_manifest::<BoardTheme>(ctx, path) {
+            Ok(mut theme) => {
+                theme.id = file_stem(path);
+                themes.push(theme);
+            }
+            Err(e) => println!("Could not read board theme {}: {}", path.display(), e)
+        }
+    }
+
+    if themes.is_empty() {
+        themes.push(BoardTheme::classic());
+    }
+    themes.sort_by(|a, b| a.name.cmp(&b.name));
+    themes
+}
+
+//Every piece set with a readable manifest, sorted by name
+pub(crate) fn piece_sets(ctx: &mut Context) -> Vec<PieceSet> {
+    let paths: Vec<_> = filesystem::read_dir(ctx, PIECE_SET_DIRECTORY).map(|paths| paths.collect()).unwrap_or_default();
+    let directories: Vec<_> = paths.into_iter().filter(|path| filesystem::is_dir(ctx, path)).collect();
+
+    let mut sets = Vec::new();
+    for path in &directories {
+        match read_manifest::<PieceSet>(ctx, &path.join(PIECE_SET_MANIFEST)) {
+            Ok(mut set) => {
+                set.id = file_stem(path);
+                set.directory = path.to_string_lossy().to_string();
+                sets.push(set);
+            }
+            Err(e) => println!("Could not read piece set {}: {}", path.display(), e)
+        }
+    }
+
+    if sets.is_empty() {
+        sets.push(PieceSet::classic());
+    }
+    sets.sort_by(|a, b| a.name.cmp(&b.name));
+    sets
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MenuChange {
+    None,
+    BoardTheme,
+    PieceSet
+}
+
+//The settings menu opened with T, where the board theme and piece set are picked with the arrow keys
+pub(crate) struct ThemeMenu {
+    board_themes: Vec<BoardTheme>,
+    piece_sets: Vec<PieceSet>,
+    board_theme: usize,
+    piece_set: usize,
+    row: usize,
+    open: bool
+}
+
+impl ThemeMenu {
+    //Starts with the themes of the given ids, or the first ones if they don't exist
+    pub(crate) fn load(ctx: &mut Context, board_theme: &str, piece_set: &str) -> Self {
+        let board_themes = board_themes(ctx);
+        let piece_sets = piece_sets(ctx);
+
+        let board_theme = board_themes.iter().position(|theme| theme.id == board_theme).unwrap_or_else(|| {
+            println!("No board theme {}, using {}", board_theme, board_themes[0].id);
+            0
+        });
+        let piece_set = piece_sets.iter().position(|set| set.id == piece_set).unwrap_or_else(|| {
+            println!("No piece set {}, using {}", piece_set, piece_sets[0].id);
+            0
+        });
+
+        ThemeMenu {
+            board_themes,
+            piece_sets,
+            board_theme,
+            piece_set,
+            row: 0,
+            open: false
+        }
+    }
+
+    pub(crate) fn get_board_theme(&self) -> &BoardTheme {
+        &self.board_themes[self.board_theme]
+    }
+
+    pub(crate) fn get_piece_set(&self) -> &PieceSet {
+        &self.piece_sets[self.piece_set]
+    }
+
+    pub(crate) fn is_open(&self) -> bool {
+        self.open
+    }
+
+    pub(crate) fn toggle(&mut self) {
+        self.open = !self.open;
+    }
+
+    //Up and down pick a row, left and right change it and Escape closes the menu
+    pub(crate) fn key_pressed(&mut self, keycode: KeyCode) -> MenuChange {
+        let step = |index: usize, count: usize, forward: bool| if forward { (index + 1) % count } else { (index + count - 1) % count };
+
+        match keycode {
+            KeyCode::Up | KeyCode::Down => self.row = 1 - self.row,
+            KeyCode::Left | KeyCode::Right if self.row == 0 => {
+                self.board_theme = step(self.board_theme, self.board_themes.len(), keycode == KeyCode::Right);
+                return MenuChange::BoardTheme;
+            }
+            KeyCode::Left | KeyCode::Right => {
+                self.piece_set = step(self.piece_set, self.piece_sets.len(), keycode == KeyCode::Right);
+                return MenuChange::PieceSet;
+            }
+            KeyCode::Escape | KeyCode::Return | KeyCode::T => self.open = false,
+            _ => {}
+        }
+
+        MenuChange::None
+    }
+
+    //The rows of the menu and which one is selected
+    pub(crate) fn lines(&self) -> (Vec<String>, usize) {
+        (vec![format!("Board: < {} >", self.get_board_theme().name), format!("Pieces: < {} >", self.get_piece_set().name)], self.row)
+    }
+}
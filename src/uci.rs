@@ -0,0 +1,376 @@
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::Duration;
+use schackmotor::Board;
+use crate::NotatedMove;
+use crate::clock::Clock;
+use crate::fen::Fen;
+use crate::notation;
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+const MOVE_TIME_MS: u64 = 1000;
+const MAX_PV_LENGTH: usize = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Score {
+    Centipawns(i32),
+    Mate(i32) //moves until mate, negative when Black is the one mating
+}
+
+//The latest search info the engine printed, with the score from White's point of view
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Analysis {
+    pub(crate) depth: u32,
+    pub(crate) score: Option<Score>,
+    pub(crate) pv: Vec<String> //in UCI notation
+}
+
+impl Analysis {
+    pub(crate) fn score_text(&self) -> String {
+        match self.score {
+            Some(Score::Centipawns(centipawns)) => format!("{:+.2}", centipawns as f32 / 100.0),
+            Some(Score::Mate(moves)) => format!("#{}", moves),
+            None => "?".to_string()
+        }
+    }
+}
+
+//Drives an engine speaking the Universal Chess Interface, running as a subprocess
+pub(crate) struct UciEngine {
+    name: String,
+    child: Child,
+    stdin: ChildStdin,
+    replies: mpsc::Receiver<String>, //everything the engine prints except search info
+    analysis: Arc<Mutex<Analysis>>,
+    black_to_move: Arc<Mutex<bool>>, //UCI scores are for the side to move, analysis is kept for White
+    stopped_searches: Arc<Mutex<usize>>, //searches told to stop that haven't printed their bestmove yet
+    searching: bool
+}
+
+impl UciEngine {
+    pub(crate) fn start(path: &str) -> Result<Self, String> {
+        let mut child = Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("Could not start engine {}: {}", path, e))?;
+
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let (sender, replies) = mpsc::channel();
+        let analysis = Arc::new(Mutex::new(Analysis::default()));
+        let black_to_move = Arc::new(Mutex::new(false));
+        let stopped_searches = Arc::new(Mutex::new(0));
+
+        let analysis_ref = analysis.clone();
+        let black_to_move_ref = black_to_move.clone();
+        let stopped_searches_ref = stopped_searches.clone();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break
+                };
+
+                //What a stopped search still prints is about a position that is gone
+                {
+                    let mut stopped_searches = stopped_searches_ref.lock().unwrap();
+                    if *stopped_searches > 0 {
+                        if line.starts_with("bestmove") {
+                            *stopped_searches -= 1;
+                        }
+                        if line.starts_with("bestmove") || line.starts_with("info ") {
+                            continue;
+                        }
+                    }
+                }
+
+                if line.starts_with("info ") {
+                    let black_to_move = *black_to_move_ref.lock().unwrap();
+                    read_info(&line, black_to_move, &mut analysis_ref.lock().unwrap());
+                } else if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut engine = UciEngine {
+            name: path.to_string(),
+            child,
+            stdin,
+            replies,
+            analysis,
+            black_to_move,
+            stopped_searches,
+            searching: false
+        };
+
+        engine.send("uci")?;
+        loop {
+            match engine.wait_for_reply(HANDSHAKE_TIMEOUT)? {
+                Some(reply) if reply == "uciok" => break,
+                Some(reply) if reply.starts_with("id name ") => engine.name = reply["id name ".len()..].trim().to_string(),
+                Some(_) => {}
+                None => return Err(format!("{} doesn't speak UCI", path))
+            }
+        }
+        engine.send("ucinewgame")?;
+        engine.send("isready")?;
+        engine.wait_for("readyok")?;
+
+        Ok(engine)
+    }
+
+    pub(crate) fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub(crate) fn get_analysis(&self) -> Analysis {
+        self.analysis.lock().unwrap().clone()
+    }
+
+    fn send(&mut self, command: &str) -> Result<(), String> {
+        writeln!(self.stdin, "{}", command).and_then(|_| self.stdin.flush())
+            .map_err(|e| format!("Could not talk to {}: {}", self.name, e))
+    }
+
+    //None once the timeout has passed, an error if the engine has quit
+    fn wait_for_reply(&self, timeout: Duration) -> Result<Option<String>, String> {
+        match self.replies.recv_timeout(timeout) {
+            Ok(reply) => Ok(Some(reply)),
+            Err(mpsc::RecvTimeoutError::Timeout) => Ok(None),
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(format!("{} has quit", self.name))
+        }
+    }
+
+    fn wait_for(&self, prefix: &str) -> Result<String, String> {
+        loop {
+            match self.wait_for_reply(HANDSHAKE_TIMEOUT)? {
+                Some(reply) if reply.starts_with(prefix) => return Ok(reply),
+                Some(_) => {}
+                None => return Err(format!("{} didn't answer with {}", self.name, prefix))
+            }
+        }
+    }
+
+    fn set_position(&mut self, start_position: &Fen, moves: &[NotatedMove]) -> Result<(), String> {
+        self.stop()?;
+
+        let mut command = if start_position.is_standard() {
+            "position startpos".to_string()
+        } else {
+            format!("position fen {}", start_position)
+        };
+        if !moves.is_empty() {
+            command.push_str(" moves");
+            for mov in moves {
+                command.push(' ');
+                command.push_str(&to_uci(mov));
+            }
+        }
+
+        let black_to_move = (start_position.get_to_move() == schackmotor::Color::Black) != (moves.len() % 2 == 1);
+        *self.black_to_move.lock().unwrap() = black_to_move;
+        *self.analysis.lock().unwrap() = Analysis::default();
+
+        self.send(&command)
+    }
+
+    //Ends a running search without waiting for it, the move it found is thrown away when it arrives
+    fn stop(&mut self) -> Result<(), String> {
+        if self.searching {
+            self.searching = false;
+            *self.stopped_searches.lock().unwrap() += 1;
+            self.send("stop")?;
+        }
+        Ok(())
+    }
+
+    //Searches the position for as long as `go` says and returns the move the engine picked
+    pub(crate) fn best_move(&mut self, start_position: &Fen, moves: &[NotatedMove], go: &str) -> Result<NotatedMove, String> {
+        self.set_position(start_position, moves)?;
+        self.send(go)?;
+
+        loop {
+            match self.replies.recv() {
+                Ok(reply) if reply.starts_with("bestmove ") => {
+                    let mov = reply.split_whitespace().nth(1).unwrap_or("");
+                    return from_uci(mov).map_err(|e| format!("{} played {}: {}", self.name, mov, e));
+                }
+                Ok(_) => {}
+                Err(_) => return Err(format!("{} has quit", self.name))
+            }
+        }
+    }
+
+    //Starts analysing the position until it changes, the results are read with get_analysis
+    pub(crate) fn analyse(&mut self, start_position: &Fen, moves: &[NotatedMove]) -> Result<(), String> {
+        self.set_position(start_position, moves)?;
+        self.send("go infinite")?;
+        self.searching = true;
+        Ok(())
+    }
+}
+
+impl Drop for UciEngine {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        let _ = self.child.kill();
+    }
+}
+
+//The go command for a search, with the remaining time on the clocks if there are any
+pub(crate) fn go_command(clock: Option<&Clock>) -> String {
+    match clock {
+        Some(clock) => {
+            let state = clock.state();
+            let increment = clock.get_time_control().increment().as_millis();
+            format!("go wtime {} btime {} winc {} binc {}", state.white_ms, state.black_ms, increment, increment)
+        }
+        None => format!("go movetime {}", MOVE_TIME_MS)
+    }
+}
+
+//UCI writes moves in long algebraic notation without separators, like "e2e4" or "e7e8q"
+pub(crate) fn to_uci(mov: &NotatedMove) -> String {
+    format!("{}{}{}", mov.start_position, mov.end_position,
+            mov.promotes_to.as_ref().map_or("".to_string(), |piece| piece.to_lowercase()))
+}
+
+pub(crate) fn from_uci(text: &str) -> Result<NotatedMove, String> {
+    NotatedMove::parse(text)
+}
+
+//The first moves of a principal variation in SAN, as far as they can be played on `board`
+pub(crate) fn pv_to_san(board: &Board, pv: &[String]) -> Vec<String> {
+    let mut board = board.clone();
+    let mut sans = Vec::new();
+
+    for uci in pv.iter().take(MAX_PV_LENGTH) {
+        let mov = match from_uci(uci) {
+            Ok(mov) => mov,
+            Err(_) => break
+        };
+        let san = match notation::to_san(&board, &mov) {
+            Ok(san) => san,
+            Err(_) => break
+        };
+        if board.take_move(mov.to_string()).is_err() {
+            break;
+        }
+        sans.push(san + notation::check_suffix(&board.get_game_state()));
+    }
+
+    sans
+}
+
+//Takes the depth, score and principal variation from an info line, keeping the old ones for
+//info lines that don't have them
+fn read_info(line: &str, black_to_move: bool, analysis: &mut Analysis) {
+    let mut tokens = line.split_whitespace().skip(1);
+
+    while let Some(token) = tokens.next() {
+        match token {
+            "depth" => {
+                if let Some(depth) = tokens.next().and_then(|depth| depth.parse().ok()) {
+                    analysis.depth = depth;
+                }
+            }
+            "score" => {
+                let kind = tokens.next();
+                let value = tokens.next().and_then(|value| value.parse::<i32>().ok());
+                let sign = if black_to_move { -1 } else { 1 };
+                match (kind, value) {
+                    (Some("cp"), Some(centipawns)) => analysis.score = Some(Score::Centipawns(sign * centipawns)),
+                    (Some("mate"), Some(moves)) => analysis.score = Some(Score::Mate(sign * moves)),
+                    _ => {}
+                }
+            }
+            "pv" => {
+                analysis.pv = tokens.by_ref().map(|mov| mov.to_string()).collect();
+            }
+            //The rest of the line is free text
+            "string" => break,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uci_round_trip() {
+        for text in &["e2e4", "e7e8q", "a2a1n"] {
+            assert_eq!(to_uci(&from_uci(text).unwrap()), *text);
+        }
+        assert_eq!(to_uci(&NotatedMove::new("b7".to_string(), "b8".to_string(), Some("R".to_string()))), "b7b8r");
+        assert!(from_uci("e7e8k").is_err());
+    }
+
+    #[test]
+    fn info_scores_are_from_whites_point_of_view() {
+        let mut analysis = Analysis::default();
+        read_info("info depth 12 score cp 35 nodes 1000 pv e2e4 e7e5", false, &mut analysis);
+        assert_eq!(analysis.depth, 12);
+        assert_eq!(analysis.score, Some(Score::Centipawns(35)));
+        assert_eq!(analysis.pv, vec!["e2e4".to_string(), "e7e5".to_string()]);
+
+        read_info("info depth 13 score cp 35", true, &mut analysis);
+        assert_eq!(analysis.score, Some(Score::Centipawns(-35)));
+        read_info("info score mate 3", true, &mut analysis);
+        assert_eq!(analysis.score, Some(Score::Mate(-3)));
+        assert_eq!(analysis.depth, 13);
+    }
+
+    #[cfg(unix)]
+    mod scripted {
+        use std::os::unix::fs::PermissionsExt;
+        use std::path::PathBuf;
+        use super::super::*;
+
+        //Answers the handshake and plays e7e8q whatever the position
+        const SCRIPT: &str = "#!/bin/sh
+while read line; do
+    case \"$line\" in
+        uci) echo 'id name Scripted engine'; echo 'uciok' ;;
+        isready) echo 'readyok' ;;
+        go*) echo 'info depth 3 score cp 25 pv e7e8q'; echo 'bestmove e7e8q' ;;
+        quit) exit 0 ;;
+    esac
+done
+";
+
+        //The script on disk, removed again when the test is done with it
+        struct ScriptFile(PathBuf);
+
+        impl ScriptFile {
+            fn write(name: &str) -> Self {
+                let path = std::env::temp_dir().join(format!("schack_{}_{}.sh", name, std::process::id()));
+                std::fs::write(&path, SCRIPT).unwrap();
+                std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+                ScriptFile(path)
+            }
+        }
+
+        impl Drop for ScriptFile {
+            fn drop(&mut self) {
+                let _ = std::fs::remove_file(&self.0);
+            }
+        }
+
+        #[test]
+        fn best_move_from_scripted_engine() {
+            let script = ScriptFile::write("best_move");
+            let mut engine = UciEngine::start(script.0.to_str().unwrap()).unwrap();
+            assert_eq!(engine.get_name(), "Scripted engine");
+
+            let mov = engine.best_move(&Fen::standard(), &[], "go movetime 10").unwrap();
+            assert_eq!(mov, NotatedMove::new("e7".to_string(), "e8".to_string(), Some("Q".to_string())));
+        }
+    }
+}